@@ -0,0 +1,30 @@
+//! Compares breadth first and best first search on expressions that take a few rewrites to simplify.
+//! Run with `cargo bench`.
+
+use symbolic::{parse, SearchConfig, Simplifier, Strategy};
+
+const EXPRESSIONS: &[&str] = &[
+  "(a^2+2*a*b+b^2)/(a+b)",
+  "(x*y+x*z)/x",
+  "exp(ln(x*y))/x",
+  "sin(x)^2+cos(x)^2+x*0",
+  "(a+b)*(a+b)-2*a*b",
+];
+
+fn main() {
+  let strategies = [
+    ("breadth first", Strategy::BreadthFirst),
+    ("best first", Strategy::BestFirst { heuristic: None, beam_width: None }),
+    ("beam of 20", Strategy::BestFirst { heuristic: None, beam_width: Some(20) }),
+  ];
+  println!("{:<26} {:<14} {:>8} {:>8} {:>10} {:>10}  result", "expression", "strategy", "measure", "nodes",
+           "expanded", "time");
+  for e in EXPRESSIONS {
+    for (name, strategy) in strategies.iter() {
+      let config = SearchConfig { strategy: strategy.clone(), ..SearchConfig::default() };
+      let result = Simplifier::new().search(config).simplify(&parse(e).unwrap());
+      println!("{:<26} {:<14} {:>8} {:>8} {:>10} {:>10.2?}  {}", e, name, result.measure, result.graph_size,
+               result.expanded, result.elapsed, result.expression);
+    }
+  }
+}
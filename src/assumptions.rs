@@ -0,0 +1,101 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// What a variable is known to be. Every variable is real.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Domain {
+  Real,
+  NonZero,
+  NonNegative,
+  Positive,
+  Integer,
+}
+
+/// Facts about the variables of an expression, that the conditions of equivalences can rely on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Assumptions {
+  domains: BTreeMap<String, BTreeSet<Domain>>,
+}
+
+impl Assumptions {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn assume(&mut self, variable: &str, domain: Domain) {
+    self.domains.entry(variable.into()).or_default().insert(domain);
+  }
+
+  pub fn is(&self, variable: &str, domain: Domain) -> bool {
+    let domains = match self.domains.get(variable) {
+      Some(domains) => domains,
+      None => return domain == Domain::Real,
+    };
+    match domain {
+      Domain::Real => true,
+      // positive implies both
+      Domain::NonZero | Domain::NonNegative => domains.contains(&domain) || domains.contains(&Domain::Positive),
+      _ => domains.contains(&domain),
+    }
+  }
+
+  /// Adds an assumption written like `x > 0`, `x >= 0`, `x != 0`, `n integer` or `x real`.
+  pub fn assume_str(&mut self, assumption: &str) -> Result<(), String> {
+    // comparisons don't need spaces around them, so `x>0` works too
+    let comparison = [">=", "!=", ">"].iter()
+      .find_map(|op| assumption.split_once(op).map(|(variable, value)| vec![variable.trim(), op, value.trim()]));
+    let words = comparison.unwrap_or_else(|| assumption.split_whitespace().collect());
+    let domain = match words.as_slice() {
+      [_, ">", "0"] => Domain::Positive,
+      [_, ">=", "0"] => Domain::NonNegative,
+      [_, "!=", "0"] => Domain::NonZero,
+      [_, "integer"] => Domain::Integer,
+      [_, "real"] => Domain::Real,
+      _ => return Err(format!("can't assume '{}'. try 'x > 0', 'x >= 0', 'x != 0', 'x integer' or 'x real'",
+                              assumption)),
+    };
+    if words[0].is_empty() || !words[0].chars().all(|c| c.is_alphanumeric() || c == '_') {
+      return Err(format!("'{}' is not a variable", words[0]));
+    }
+    self.assume(words[0], domain);
+    Ok(())
+  }
+}
+
+impl fmt::Display for Assumptions {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut facts = Vec::new();
+    for (variable, domains) in self.domains.iter() {
+      for domain in domains.iter() {
+        facts.push(match domain {
+          Domain::Real => format!("{} is real", variable),
+          Domain::NonZero => format!("{} != 0", variable),
+          Domain::NonNegative => format!("{} >= 0", variable),
+          Domain::Positive => format!("{} > 0", variable),
+          Domain::Integer => format!("{} is an integer", variable),
+        });
+      }
+    }
+    write!(f, "{}", facts.join(", "))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_assume_str() {
+    let mut known = Assumptions::new();
+    for assumption in &["x>0", "y >=0", "z!= 0", "n integer"] {
+      known.assume_str(assumption).unwrap();
+    }
+    assert!(known.is("x", Domain::Positive));
+    assert!(known.is("y", Domain::NonNegative));
+    assert!(known.is("z", Domain::NonZero));
+    assert!(known.is("n", Domain::Integer));
+    for assumption in &["x > = 0", ">0", "x+1 > 0", "x < 0", "x"] {
+      assert!(known.assume_str(assumption).is_err(), "{}", assumption);
+    }
+  }
+}
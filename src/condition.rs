@@ -0,0 +1,153 @@
+use crate::assumptions::{Assumptions, Domain};
+use crate::parser::{Expression, Function, Rational};
+use num_integer::Integer;
+use num_traits::{Signed, Zero};
+use std::fmt;
+
+/// A side condition that must hold for an equivalence to be sound, e.g. `a != 0` for `a/a = 1`.
+/// In an Equivalence the conditions are about its pattern variables. Once the pattern has matched,
+/// they are instantiated with what the variables matched, and checked against that.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Condition {
+  NonZero(Expression),
+  Positive(Expression),
+  NonNegative(Expression),
+  Integer(Expression),
+  /// The expression has no variables in it.
+  Constant(Expression),
+  /// At least one of the conditions holds.
+  Any(Vec<Condition>),
+}
+
+impl fmt::Display for Condition {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Condition::NonZero(e) => write!(f, "{} != 0", e),
+      Condition::Positive(e) => write!(f, "{} > 0", e),
+      Condition::NonNegative(e) => write!(f, "{} >= 0", e),
+      Condition::Integer(e) => write!(f, "{} is an integer", e),
+      Condition::Constant(e) => write!(f, "{} is constant", e),
+      Condition::Any(conditions) => {
+        let conditions: Vec<String> = conditions.iter().map(|c| format!("({})", c)).collect();
+        write!(f, "{}", conditions.join(" or "))
+      },
+    }
+  }
+}
+
+fn value(e: &Expression) -> Option<Rational> {
+  e.eval_const().and_then(|c| c.unwrap_constant())
+}
+
+fn in_domain(c: &Rational, domain: Domain) -> bool {
+  match domain {
+    Domain::Real => true,
+    Domain::NonZero => !c.is_zero(),
+    Domain::NonNegative => !c.is_negative(),
+    Domain::Positive => c.is_positive(),
+    Domain::Integer => c.is_integer(),
+  }
+}
+
+// Whether e is certainly in the domain (wherever it is defined), from its structure and the assumptions
+// about its variables. false means it couldn't be shown, not that it is false.
+fn proven(e: &Expression, domain: Domain, assumptions: &Assumptions) -> bool {
+  if let Some(c) = value(e) {
+    return in_domain(&c, domain);
+  }
+  let is = |e: &Expression, domain: Domain| proven(e, domain, assumptions);
+  let shown = match (domain, e) {
+    (_, Expression::Variable(v)) => assumptions.is(v, domain),
+    (Domain::Real, _) => true,
+    (Domain::Positive, Expression::Apply(Function::Exp, _)) => true,
+    (Domain::Positive, Expression::Apply(Function::Sqrt, a)) => is(a, Domain::Positive),
+    (Domain::Positive, Expression::Apply(Function::Abs, a)) => is(a, Domain::NonZero),
+    (Domain::Positive, Expression::Sum(a, b)) =>
+      (is(a, Domain::Positive) && is(b, Domain::NonNegative)) || (is(a, Domain::NonNegative) && is(b, Domain::Positive)),
+    (Domain::Positive, Expression::Product(a, b)) | (Domain::Positive, Expression::Quotient(a, b)) =>
+      is(a, Domain::Positive) && is(b, Domain::Positive),
+    (Domain::Positive, Expression::Power(a, _)) => is(a, Domain::Positive),
+    (Domain::NonZero, Expression::Apply(Function::Abs, a)) => is(a, Domain::NonZero),
+    (Domain::NonZero, Expression::Product(a, b)) | (Domain::NonZero, Expression::Quotient(a, b)) =>
+      is(a, Domain::NonZero) && is(b, Domain::NonZero),
+    (Domain::NonZero, Expression::Power(a, _)) => is(a, Domain::NonZero),
+    (Domain::NonNegative, Expression::Apply(Function::Abs, _)) |
+    (Domain::NonNegative, Expression::Apply(Function::Sqrt, _)) => true,
+    (Domain::NonNegative, Expression::Sum(a, b)) | (Domain::NonNegative, Expression::Product(a, b)) |
+    (Domain::NonNegative, Expression::Quotient(a, b)) => is(a, Domain::NonNegative) && is(b, Domain::NonNegative),
+    // even powers
+    (Domain::NonNegative, Expression::Power(a, b)) => is(a, Domain::NonNegative) || match value(b) {
+      Some(c) => c.is_integer() && c.to_integer().is_even(),
+      None => false,
+    },
+    (Domain::Integer, Expression::Sum(a, b)) | (Domain::Integer, Expression::Difference(a, b)) |
+    (Domain::Integer, Expression::Product(a, b)) => is(a, Domain::Integer) && is(b, Domain::Integer),
+    (Domain::Integer, Expression::Power(a, b)) =>
+      is(a, Domain::Integer) && value(b).is_some_and(|c| c.is_integer() && !c.is_negative()),
+    _ => false,
+  };
+  // anything positive is also nonzero and nonnegative
+  shown || (matches!(domain, Domain::NonZero | Domain::NonNegative) && is(e, Domain::Positive))
+}
+
+impl Condition {
+  /// The condition with each subject transformed by f, e.g. to replace pattern variables with what they matched.
+  pub fn map<F: Fn(&Expression) -> Expression>(&self, f: &F) -> Condition {
+    match self {
+      Condition::NonZero(e) => Condition::NonZero(f(e)),
+      Condition::Positive(e) => Condition::Positive(f(e)),
+      Condition::NonNegative(e) => Condition::NonNegative(f(e)),
+      Condition::Integer(e) => Condition::Integer(f(e)),
+      Condition::Constant(e) => Condition::Constant(f(e)),
+      Condition::Any(conditions) => Condition::Any(conditions.iter().map(|c| c.map(f)).collect()),
+    }
+  }
+
+  /// Some(true) if the condition certainly holds, Some(false) if it certainly doesn't,
+  /// and None if that depends on the values of variables that nothing is assumed about.
+  pub fn check(&self, assumptions: &Assumptions) -> Option<bool> {
+    let holds = |e: &Expression, domain: Domain| match value(e) {
+      Some(c) => Some(in_domain(&c, domain)),
+      None if proven(e, domain, assumptions) => Some(true),
+      None => None,
+    };
+    match self {
+      Condition::NonZero(e) => holds(e, Domain::NonZero),
+      Condition::Positive(e) => holds(e, Domain::Positive),
+      Condition::NonNegative(e) => holds(e, Domain::NonNegative),
+      Condition::Integer(e) => holds(e, Domain::Integer),
+      Condition::Constant(e) => Some(e.free_variables().is_empty()),
+      Condition::Any(conditions) => {
+        let checked: Vec<Option<bool>> = conditions.iter().map(|c| c.check(assumptions)).collect();
+        if checked.contains(&Some(true)) {
+          Some(true)
+        } else if checked.iter().all(|c| *c == Some(false)) {
+          Some(false)
+        } else {
+          None
+        }
+      },
+    }
+  }
+
+  /// What is left to assume for the condition to hold: None if it certainly doesn't hold,
+  /// and otherwise the parts of it that couldn't be checked.
+  pub fn undischarged(&self, assumptions: &Assumptions) -> Option<Vec<Condition>> {
+    match self.check(assumptions) {
+      Some(true) => Some(vec![]),
+      Some(false) => None,
+      None => match self {
+        // drop the alternatives that can't hold
+        Condition::Any(conditions) => {
+          let mut open: Vec<Condition> = conditions.iter().filter(|c| c.check(assumptions) != Some(false)).cloned().collect();
+          if open.len() == 1 {
+            Some(open.pop().unwrap().undischarged(assumptions)?)
+          } else {
+            Some(vec![Condition::Any(open)])
+          }
+        },
+        _ => Some(vec![self.clone()]),
+      },
+    }
+  }
+}
@@ -0,0 +1,155 @@
+use crate::parser::Expression;
+
+/// What the simplifier minimizes. Smaller is simpler.
+pub trait CostFunction: Send + Sync {
+  fn cost(&self, e: &Expression) -> i32;
+}
+
+/// Every variable, constant, operator and function counts as one.
+pub struct NodeCount;
+
+impl CostFunction for NodeCount {
+  fn cost(&self, e: &Expression) -> i32 {
+    Weighted { sum: 1, difference: 1, product: 1, quotient: 1, power: 1, function: 1, variable: 1, integer: 1,
+               fraction: 1 }.cost(e)
+  }
+}
+
+/// How many operators and functions there are, so variables and constants are free.
+pub struct OperationCount;
+
+impl CostFunction for OperationCount {
+  fn cost(&self, e: &Expression) -> i32 {
+    Weighted { sum: 1, difference: 1, product: 1, quotient: 1, power: 1, function: 1, variable: 0, integer: 0,
+               fraction: 0 }.cost(e)
+  }
+}
+
+/// How deep the expression tree is, e.g. 1 for x and 3 for sin(x+1).
+pub struct Depth;
+
+impl CostFunction for Depth {
+  fn cost(&self, e: &Expression) -> i32 {
+    match e {
+      Expression::Constant(_) | Expression::Variable(_) => 1,
+      Expression::Sum(a, b) | Expression::Difference(a, b) | Expression::Product(a, b) |
+      Expression::Quotient(a, b) | Expression::Power(a, b) => self.cost(a).max(self.cost(b)) + 1,
+      Expression::Apply(_, a) => self.cost(a) + 1,
+    }
+  }
+}
+
+/// Adds up a cost for every node of the expression, by what kind of node it is.
+/// The default is `measure::measure`, and e.g. `Weighted { quotient: 10, ..Weighted::default() }` avoids division.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weighted {
+  pub sum: i32,
+  pub difference: i32,
+  pub product: i32,
+  pub quotient: i32,
+  pub power: i32,
+  pub function: i32,
+  pub variable: i32,
+  pub integer: i32,
+  pub fraction: i32,
+}
+
+impl Default for Weighted {
+  fn default() -> Self {
+    // a fraction is more than an integer, but less than the quotient it was evaluated from
+    Weighted { sum: 1, difference: 1, product: 1, quotient: 1, power: 1, function: 1, variable: 2, integer: 1,
+               fraction: 2 }
+  }
+}
+
+impl CostFunction for Weighted {
+  fn cost(&self, e: &Expression) -> i32 {
+    match e {
+      Expression::Constant(c) if !c.is_integer() => self.fraction,
+      Expression::Constant(_) => self.integer,
+      Expression::Variable(_) => self.variable,
+      Expression::Sum(a, b) => self.cost(a) + self.cost(b) + self.sum,
+      Expression::Difference(a, b) => self.cost(a) + self.cost(b) + self.difference,
+      Expression::Product(a, b) => self.cost(a) + self.cost(b) + self.product,
+      Expression::Quotient(a, b) => self.cost(a) + self.cost(b) + self.quotient,
+      Expression::Power(a, b) => self.cost(a) + self.cost(b) + self.power,
+      Expression::Apply(_, a) => self.cost(a) + self.function,
+    }
+  }
+}
+
+// Added for each sum a preference counts against, so it outweighs the few nodes that e.g. distributing adds.
+const FORM_PENALTY: i32 = 10;
+
+/// Prefers sums of products, like a*b+a*c over a*(b+c): every sum or difference multiplied, divided
+/// or raised to a power costs extra. Otherwise like `Weighted::default()`.
+pub struct PreferExpanded;
+
+impl CostFunction for PreferExpanded {
+  fn cost(&self, e: &Expression) -> i32 {
+    Weighted::default().cost(e) + FORM_PENALTY * unexpanded_sums(e, false)
+  }
+}
+
+// How many sums and differences are operands of a product, quotient or power, or inside one.
+fn unexpanded_sums(e: &Expression, inside_product: bool) -> i32 {
+  match e {
+    Expression::Constant(_) | Expression::Variable(_) => 0,
+    Expression::Sum(a, b) | Expression::Difference(a, b) =>
+      inside_product as i32 + unexpanded_sums(a, inside_product) + unexpanded_sums(b, inside_product),
+    Expression::Product(a, b) | Expression::Quotient(a, b) | Expression::Power(a, b) =>
+      unexpanded_sums(a, true) + unexpanded_sums(b, true),
+    // the argument of a function is expanded on its own
+    Expression::Apply(_, a) => unexpanded_sums(a, false),
+  }
+}
+
+/// Prefers products of sums, like a*(b+c) over a*b+a*c: every sum or difference that isn't multiplied, divided
+/// or raised to a power costs extra. Otherwise like `Weighted::default()`.
+pub struct PreferFactored;
+
+impl CostFunction for PreferFactored {
+  fn cost(&self, e: &Expression) -> i32 {
+    Weighted::default().cost(e) + FORM_PENALTY * outer_sums(e)
+  }
+}
+
+// How many sums and differences aren't inside a product, quotient or power.
+fn outer_sums(e: &Expression) -> i32 {
+  match e {
+    Expression::Constant(_) | Expression::Variable(_) => 0,
+    Expression::Sum(a, b) | Expression::Difference(a, b) => 1 + outer_sums(a) + outer_sums(b),
+    Expression::Product(_, _) | Expression::Quotient(_, _) | Expression::Power(_, _) => 0,
+    Expression::Apply(_, a) => outer_sums(a),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::{parse, ParseError};
+
+  #[test]
+  fn test_costs() -> Result<(), ParseError> {
+    let e = parse("sin(x+1)/2")?;
+    assert_eq!(NodeCount.cost(&e), 6);
+    assert_eq!(OperationCount.cost(&e), 3);
+    assert_eq!(Depth.cost(&e), 4);
+    assert_eq!(Weighted::default().cost(&e), 7);
+    assert_eq!(Weighted { quotient: 10, ..Weighted::default() }.cost(&e), 16);
+    assert_eq!(Weighted::default().cost(&parse("[1/2]")?), 2);
+    Ok(())
+  }
+
+  #[test]
+  fn test_forms() -> Result<(), ParseError> {
+    let (factored, expanded) = (parse("x*(y+z)")?, parse("x*y+x*z")?);
+    assert!(PreferFactored.cost(&factored) < PreferFactored.cost(&expanded));
+    assert!(PreferExpanded.cost(&expanded) < PreferExpanded.cost(&factored));
+    // only a+b is raised to a power, the sum inside ln doesn't count
+    assert_eq!(PreferExpanded.cost(&parse("(a+b)^2+ln(c+d)")?), 14 + FORM_PENALTY);
+    // only the outer sum counts
+    assert_eq!(PreferFactored.cost(&parse("a+b*(c+d)")?), 11 + FORM_PENALTY);
+    Ok(())
+  }
+}
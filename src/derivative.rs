@@ -0,0 +1,130 @@
+use crate::assumptions::Assumptions;
+use crate::cost::CostFunction;
+use crate::measure::{find_min_equivalent_expr, SearchConfig, Silent};
+use crate::parser::{Expression, Function, Rational};
+use crate::rules::Rules;
+use crate::tree_transform::simplify_via_forward_transform;
+use crate::c;
+use num_traits::One;
+use std::ops::Deref;
+
+/// The derivative of e with respect to var, straight from the textbook rules, so full of `*1` and `+0`.
+/// See `simplified_derivative` for something readable.
+pub fn derivative(e: &Expression, var: &str) -> Expression {
+  if !e.free_variables().contains(var) {
+    return c!(0);
+  }
+  let d = |e: &Expression| derivative(e, var);
+  match e {
+    Expression::Constant(_) => c!(0),
+    Expression::Variable(_) => c!(1),
+    Expression::Sum(a, b) => d(a) + d(b),
+    Expression::Difference(a, b) => d(a) - d(b),
+    // (a*b)' = a'*b + a*b'
+    Expression::Product(a, b) => d(a) * b.deref().clone() + a.deref().clone() * d(b),
+    // (a/b)' = (a'*b - a*b')/b^2
+    Expression::Quotient(a, b) =>
+      (d(a) * b.deref().clone() - a.deref().clone() * d(b)) / (b.deref().clone() ^ c!(2)),
+    Expression::Power(a, b) => {
+      let (a, b) = (a.deref().clone(), b.deref().clone());
+      if !b.free_variables().contains(var) {
+        // (a^b)' = b*a^(b-1)*a'
+        let b_minus_one = match b.unwrap_constant() {
+          Some(b) => Expression::Constant(b - Rational::one()),
+          None => b.clone() - c!(1),
+        };
+        b * (a.clone() ^ b_minus_one) * d(&a)
+      } else {
+        // a^b = exp(b*ln(a)), so (a^b)' = a^b*(b'*ln(a) + b*a'/a)
+        let da = d(&a);
+        let db = d(&b);
+        (a.clone() ^ b.clone()) * (db * Function::Ln.apply(a.clone()) + b * da / a)
+      }
+    },
+    // chain rule: f(a)' = f'(a)*a'
+    Expression::Apply(func, a) => {
+      let a = a.deref().clone();
+      let outer = match func {
+        Function::Sin => Function::Cos.apply(a.clone()),
+        Function::Cos => c!(-1) * Function::Sin.apply(a.clone()),
+        Function::Tan => c!(1) / (Function::Cos.apply(a.clone()) ^ c!(2)),
+        Function::Exp => Function::Exp.apply(a.clone()),
+        Function::Ln => c!(1) / a.clone(),
+        Function::Sqrt => c!(1) / (c!(2) * Function::Sqrt.apply(a.clone())),
+        Function::Abs => Function::Abs.apply(a.clone()) / a.clone(),
+      };
+      outer * d(&a)
+    },
+  }
+}
+
+// The product, quotient and chain rules leave a lot for the search to try, so even a derivative of a quotient
+// may never finish searching. Unless the config has a node or time budget, the search stops after this many.
+const DEFAULT_NODE_BUDGET: usize = 2_000;
+
+/// The derivative of e with respect to var, simplified by the rules to the cheapest found within the budgets,
+/// or within 2000 expressions if config has neither a node nor a time budget.
+pub fn simplified_derivative(e: &Expression, var: &str, rules: &Rules, known: &Assumptions, cost: &dyn CostFunction,
+                             config: &SearchConfig) -> Expression {
+  let (raw, _) = simplify_via_forward_transform(derivative(e, var), &rules.simple, known);
+  let budgeted;
+  let config = if config.max_nodes.is_none() && config.timeout.is_none() {
+    budgeted = SearchConfig { max_nodes: Some(DEFAULT_NODE_BUDGET), ..config.clone() };
+    &budgeted
+  } else {
+    config
+  };
+  find_min_equivalent_expr(raw, rules, known, cost, config, &mut Silent).expression
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cost::Weighted;
+  use crate::parser::{parse, ParseError};
+  use std::collections::HashMap;
+
+  // Compares the derivative against a central finite difference at a few points.
+  fn assert_derivative_numerically(e: &str) -> Result<(), ParseError> {
+    let e = parse(e)?;
+    let de = derivative(&e, "x");
+    let h = 1e-6;
+    let env = |x: f64| -> HashMap<String, f64> { vec![("x".to_string(), x), ("y".to_string(), 1.7)].into_iter().collect() };
+    for x in [0.3, 0.7, 1.3, 2.1] {
+      let numeric = (e.evaluate(&env(x + h)).unwrap() - e.evaluate(&env(x - h)).unwrap()) / (2. * h);
+      let symbolic = de.evaluate(&env(x)).unwrap();
+      assert!((numeric - symbolic).abs() < 1e-4 * symbolic.abs().max(1.), "d/dx {} = {} is {} at x={}, not {}",
+              e, de, symbolic, x, numeric);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_derivatives_numerically() -> Result<(), ParseError> {
+    for e in ["x^3-2*x+1", "x*y+sin(x)*x^2", "(x+1)/(x^2+y)", "x^x", "2^x", "exp(ln(x)*y)",
+              "sqrt(x^2+1)", "tan(x)/cos(x)", "abs(x-1)", "ln(x*y+1)^(1/3)"] {
+      assert_derivative_numerically(e)?;
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_constant_derivative() -> Result<(), ParseError> {
+    assert_eq!(derivative(&parse("y^2+sin(y)")?, "x"), c!(0));
+    Ok(())
+  }
+
+  #[test]
+  fn test_simplified_derivative() -> Result<(), ParseError> {
+    let d = |e: &str| -> Result<Expression, ParseError> {
+      Ok(simplified_derivative(&parse(e)?, "x", &Rules::default(), &Assumptions::new(), &Weighted::default(),
+                               &SearchConfig::default()))
+    };
+    assert_eq!(d("x^2")?, parse("2*x")?);
+    assert_eq!(d("sin(x)")?, parse("cos(x)")?);
+    assert_eq!(d("x*y")?, parse("y")?);
+    // searches within a budget, since with no budget this never finishes
+    assert_eq!(d("(x+1)/(x^2+y)")?, parse("(x^2+y-(x+1)*(2*x))/(x^2+y)^2")?);
+    Ok(())
+  }
+}
@@ -0,0 +1,596 @@
+use crate::assumptions::Assumptions;
+use crate::parser::{Expression, Function, Rational};
+use crate::rules::Rules;
+use crate::tree_transform::{self, Equivalence};
+use crate::condition::Condition;
+use crate::cost::{CostFunction, Weighted};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+// An e-graph: a set of e-classes, each of which is a set of equivalent e-nodes.
+// Instead of enumerating whole expression trees like the BFS in `measure`, the e-graph shares
+// every subterm, so applying a rule anywhere in an expression only adds the rewritten part.
+// See "egg: Fast and Extensible Equality Saturation" (Willsey et al.) for the algorithm.
+// It's what the REPL simplifies with by default. It can't say how it derived what it extracts though,
+// so the BFS is still what finds derivations, proofs and simplified derivatives.
+
+pub type Id = usize;
+
+/// The operators of `Expression`, so that e-nodes can be handled uniformly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Operator {
+  Sum,
+  Product,
+  Difference,
+  Quotient,
+  Power,
+}
+
+impl Operator {
+  fn of(e: &Expression) -> Option<(Operator, &Expression, &Expression)> {
+    match e {
+      Expression::Constant(_) | Expression::Variable(_) | Expression::Apply(_, _) => None,
+      Expression::Sum(a, b) => Some((Operator::Sum, a, b)),
+      Expression::Product(a, b) => Some((Operator::Product, a, b)),
+      Expression::Difference(a, b) => Some((Operator::Difference, a, b)),
+      Expression::Quotient(a, b) => Some((Operator::Quotient, a, b)),
+      Expression::Power(a, b) => Some((Operator::Power, a, b)),
+    }
+  }
+
+  fn build(self, a: Expression, b: Expression) -> Expression {
+    match self {
+      Operator::Sum => a + b,
+      Operator::Product => a * b,
+      Operator::Difference => a - b,
+      Operator::Quotient => a / b,
+      Operator::Power => a ^ b,
+    }
+  }
+}
+
+/// An expression node whose children are e-classes rather than expressions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ENode {
+  // Only ever a Constant or a Variable.
+  Leaf(LeafKey),
+  Binary(Operator, Id, Id),
+  Unary(Function, Id),
+}
+
+// Expression isn't Ord, so leaves are keyed by a stand-in that is.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LeafKey {
+  Constant(Rational),
+  Variable(String),
+}
+
+impl ENode {
+  fn children(&self) -> Vec<Id> {
+    match self {
+      ENode::Leaf(_) => vec![],
+      ENode::Binary(_, a, b) => vec![*a, *b],
+      ENode::Unary(_, a) => vec![*a],
+    }
+  }
+
+  fn leaf_expression(leaf: &LeafKey) -> Expression {
+    match leaf {
+      LeafKey::Constant(c) => Expression::Constant(c.clone()),
+      LeafKey::Variable(v) => Expression::Variable(v.clone()),
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+struct EClass {
+  nodes: Vec<ENode>,
+  // e-nodes that have this class as a child, and the class they belong to.
+  parents: Vec<(ENode, Id)>,
+}
+
+/// Why `EGraph::run` stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaturationStop {
+  /// No rule can add anything new: every equivalent form reachable by the rules is in the graph.
+  Saturated,
+  NodeLimit,
+  IterationLimit,
+}
+
+#[derive(Default)]
+pub struct EGraph {
+  // union-find over e-class ids. Only canonical ids have an entry in `classes`.
+  union_find: Vec<Id>,
+  // ordered, so that extraction breaks ties between equally cheap terms deterministically
+  classes: BTreeMap<Id, EClass>,
+  // hash-consing: every canonical e-node maps to its e-class
+  memo: HashMap<ENode, Id>,
+  // classes whose parents need to be re-canonicalized to restore congruence
+  pending: Vec<Id>,
+  // conditions that couldn't be checked, and the class they were assumed to merge something into
+  assumed: Vec<(Id, Condition)>,
+}
+
+// Pattern variable assignments, from the `before` or `after` of an Equivalence to e-classes.
+type Substitution = HashMap<String, Id>;
+
+impl EGraph {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn find(&self, mut id: Id) -> Id {
+    while self.union_find[id] != id {
+      id = self.union_find[id];
+    }
+    id
+  }
+
+  pub fn node_count(&self) -> usize {
+    self.memo.len()
+  }
+
+  pub fn class_count(&self) -> usize {
+    self.classes.len()
+  }
+
+  fn canonicalize(&self, node: &ENode) -> ENode {
+    match node {
+      ENode::Leaf(_) => node.clone(),
+      ENode::Binary(op, a, b) => ENode::Binary(*op, self.find(*a), self.find(*b)),
+      ENode::Unary(f, a) => ENode::Unary(*f, self.find(*a)),
+    }
+  }
+
+  /// Adds the e-node to the graph, returning the e-class it belongs to.
+  pub fn add(&mut self, node: ENode) -> Id {
+    let node = self.canonicalize(&node);
+    if let Some(id) = self.memo.get(&node) {
+      return self.find(*id);
+    }
+    let id = self.union_find.len();
+    self.union_find.push(id);
+    for child in node.children() {
+      self.classes.get_mut(&child).unwrap().parents.push((node.clone(), id));
+    }
+    self.classes.insert(id, EClass { nodes: vec![node.clone()], parents: Vec::new() });
+    self.memo.insert(node, id);
+    id
+  }
+
+  pub fn add_expr(&mut self, e: &Expression) -> Id {
+    let node = match e {
+      Expression::Constant(c) => ENode::Leaf(LeafKey::Constant(c.clone())),
+      Expression::Variable(v) => ENode::Leaf(LeafKey::Variable(v.clone())),
+      Expression::Apply(f, a) => ENode::Unary(*f, self.add_expr(a)),
+      _ => {
+        let (op, a, b) = Operator::of(e).unwrap();
+        let a = self.add_expr(a);
+        let b = self.add_expr(b);
+        ENode::Binary(op, a, b)
+      },
+    };
+    self.add(node)
+  }
+
+  /// Merges two e-classes, returning false if they were already the same.
+  /// Congruence is only restored by the next call to `rebuild`.
+  pub fn union(&mut self, a: Id, b: Id) -> bool {
+    let (mut a, mut b) = (self.find(a), self.find(b));
+    if a == b {
+      return false;
+    }
+    // keep the class with more parents as the root, so fewer parents move
+    if self.classes[&a].parents.len() < self.classes[&b].parents.len() {
+      std::mem::swap(&mut a, &mut b);
+    }
+    self.union_find[b] = a;
+    let merged = self.classes.remove(&b).unwrap();
+    let class = self.classes.get_mut(&a).unwrap();
+    class.nodes.extend(merged.nodes);
+    class.parents.extend(merged.parents);
+    self.pending.push(a);
+    true
+  }
+
+  /// Restores the invariants that the memo only holds canonical e-nodes,
+  /// and that congruent e-nodes (same operator, equivalent children) are in the same e-class.
+  pub fn rebuild(&mut self) {
+    while !self.pending.is_empty() {
+      let pending = std::mem::take(&mut self.pending);
+      let todo: BTreeSet<Id> = pending.into_iter().map(|id| self.find(id)).collect();
+      for id in todo {
+        // an earlier repair may have merged this class into another
+        let id = self.find(id);
+        self.repair(id);
+      }
+    }
+    for id in self.classes.keys().cloned().collect::<Vec<Id>>() {
+      let mut nodes: Vec<ENode> = self.classes[&id].nodes.iter().map(|n| self.canonicalize(n)).collect();
+      nodes.sort();
+      nodes.dedup();
+      self.classes.get_mut(&id).unwrap().nodes = nodes;
+    }
+  }
+
+  fn repair(&mut self, id: Id) {
+    let parents = std::mem::take(&mut self.classes.get_mut(&id).unwrap().parents);
+    for (node, class) in parents.iter() {
+      self.memo.remove(node);
+      let node = self.canonicalize(node);
+      let class = self.find(*class);
+      self.memo.insert(node, class);
+    }
+    let mut new_parents: BTreeMap<ENode, Id> = BTreeMap::new();
+    for (node, class) in parents {
+      let node = self.canonicalize(&node);
+      if let Some(other) = new_parents.get(&node) {
+        self.union(*other, class);
+      }
+      new_parents.insert(node, self.find(class));
+    }
+    // the class may have been merged away by the unions above
+    let id = self.find(id);
+    self.classes.get_mut(&id).unwrap().parents.extend(new_parents);
+  }
+
+  /// All ways `pattern` matches some e-node of e-class `id`, extending `subst`.
+  fn ematch(&self, pattern: &Expression, id: Id, subst: &Substitution) -> Vec<Substitution> {
+    let id = self.find(id);
+    match pattern {
+      Expression::Variable(v) => match subst.get(v) {
+        Some(bound) => if self.find(*bound) == id { vec![subst.clone()] } else { vec![] },
+        None => {
+          let mut subst = subst.clone();
+          subst.insert(v.clone(), id);
+          vec![subst]
+        },
+      },
+      Expression::Constant(c) => {
+        if self.classes[&id].nodes.contains(&ENode::Leaf(LeafKey::Constant(c.clone()))) {
+          vec![subst.clone()]
+        } else {
+          vec![]
+        }
+      },
+      Expression::Apply(f, p) => {
+        let mut matches = Vec::new();
+        for node in self.classes[&id].nodes.iter() {
+          if let ENode::Unary(g, a) = node {
+            if f != g { continue; }
+            matches.extend(self.ematch(p, *a, subst));
+          }
+        }
+        matches
+      },
+      _ => {
+        let (op, p1, p2) = Operator::of(pattern).unwrap();
+        let mut matches = Vec::new();
+        for node in self.classes[&id].nodes.iter() {
+          if let ENode::Binary(node_op, a, b) = node {
+            if *node_op != op { continue; }
+            for s1 in self.ematch(p1, *a, subst) {
+              matches.extend(self.ematch(p2, *b, &s1));
+            }
+          }
+        }
+        matches
+      },
+    }
+  }
+
+  /// Adds the `after` side of a rule, with its variables replaced by the matched e-classes.
+  /// Returns None if `after` has a variable that `before` didn't bind.
+  fn instantiate(&mut self, pattern: &Expression, subst: &Substitution) -> Option<Id> {
+    let node = match pattern {
+      Expression::Variable(v) => return subst.get(v).map(|id| self.find(*id)),
+      Expression::Constant(c) => ENode::Leaf(LeafKey::Constant(c.clone())),
+      Expression::Apply(f, a) => ENode::Unary(*f, self.instantiate(a, subst)?),
+      _ => {
+        let (op, a, b) = Operator::of(pattern).unwrap();
+        let a = self.instantiate(a, subst)?;
+        let b = self.instantiate(b, subst)?;
+        ENode::Binary(op, a, b)
+      },
+    };
+    Some(self.add(node))
+  }
+
+  // The e-node as an expression, with each child replaced by the best expression of its e-class.
+  fn node_expression(&self, node: &ENode, best: &HashMap<Id, (i32, Expression)>) -> Option<Expression> {
+    match node {
+      ENode::Leaf(leaf) => Some(ENode::leaf_expression(leaf)),
+      ENode::Binary(op, a, b) => {
+        let (_, a) = best.get(&self.find(*a))?;
+        let (_, b) = best.get(&self.find(*b))?;
+        Some(op.build(a.clone(), b.clone()))
+      },
+      ENode::Unary(f, a) => {
+        let (_, a) = best.get(&self.find(*a))?;
+        Some(f.apply(a.clone()))
+      },
+    }
+  }
+
+  // The conditions of the equivalence that have to be assumed, for the best expressions of the matched e-classes,
+  // or None if a requirement doesn't certainly hold or a condition certainly fails.
+  fn assumed_conditions(&self, equiv: &Equivalence, subst: &Substitution, best: &HashMap<Id, (i32, Expression)>,
+                        known: &Assumptions) -> Option<Vec<Condition>> {
+    let assignments: HashMap<String, &Expression> = subst.iter()
+      .map(|(v, id)| (v.clone(), &best[&self.find(*id)].1))
+      .collect();
+    let instantiate = |c: &Condition| c.map(&|e| tree_transform::apply_transform(e, &assignments));
+    if !equiv.requirements.iter().all(|c| instantiate(c).check(known) == Some(true)) {
+      return None;
+    }
+    let mut assumed = Vec::new();
+    for condition in equiv.conditions.iter() {
+      assumed.extend(instantiate(condition).undischarged(known)?);
+    }
+    Some(assumed)
+  }
+
+  /// Everything assumed to merge e-classes that the e-class of root is made of, once each.
+  /// That's more than extracting one expression needs, but never less.
+  pub fn assumptions(&self, root: Id) -> Vec<Condition> {
+    let mut reachable = BTreeSet::new();
+    let mut todo = vec![self.find(root)];
+    while let Some(id) = todo.pop() {
+      if reachable.insert(id) {
+        todo.extend(self.classes[&id].nodes.iter().flat_map(|node| node.children()).map(|child| self.find(child)));
+      }
+    }
+    let assumed: Vec<&Condition> = self.assumed.iter()
+      .filter(|(id, _)| reachable.contains(&self.find(*id)))
+      .map(|(_, condition)| condition)
+      .collect();
+    let mut assumptions: Vec<Condition> = Vec::new();
+    for condition in assumed.iter() {
+      // a rule matched all over the graph assumes much the same thing many times
+      let redundant = assumptions.contains(condition) ||
+        assumed.iter().any(|other| other != condition && implies(other, condition));
+      if !redundant {
+        assumptions.push((*condition).clone());
+      }
+    }
+    assumptions
+  }
+
+  /// For every e-class, the cheapest expression it represents, according to cost.
+  pub fn extract_all(&self, cost: &dyn CostFunction) -> HashMap<Id, (i32, Expression)> {
+    let mut best: HashMap<Id, (i32, Expression)> = HashMap::new();
+    // For costs that add up over the tree, like `measure`, iterating to a fixed point finds the cheapest terms.
+    // Others, like `Depth`, get terms built from the cheapest subterms, which are only about the cheapest.
+    loop {
+      let mut changed = false;
+      for (id, class) in self.classes.iter() {
+        for node in class.nodes.iter() {
+          let candidate = match self.node_expression(node, &best) {
+            Some(candidate) => candidate,
+            None => continue,
+          };
+          let candidate_cost = cost.cost(&candidate);
+          if best.get(id).is_none_or(|(best_cost, _)| candidate_cost < *best_cost) {
+            best.insert(*id, (candidate_cost, candidate));
+            changed = true;
+          }
+        }
+      }
+      if !changed {
+        return best;
+      }
+    }
+  }
+
+  pub fn extract(&self, id: Id, cost: &dyn CostFunction) -> Expression {
+    self.extract_all(cost).remove(&self.find(id)).unwrap().1
+  }
+
+  /// Applies every equivalence, in every direction it allows, to every e-class at once,
+  /// until nothing changes or a limit is hit.
+  pub fn run(&mut self, equivalences: &[&Equivalence], known: &Assumptions, node_limit: usize, iteration_limit: usize)
+             -> SaturationStop {
+    for _ in 0..iteration_limit {
+      let mut unions = Vec::new();
+      // Closure-based equivalences can't be matched against e-classes,
+      // so they see each e-node with its children replaced by their cheapest expressions.
+      let best = self.extract_all(&Weighted::default());
+      for (id, class) in self.classes.iter() {
+        for node in class.nodes.iter() {
+          let exp = match self.node_expression(node, &best) {
+            Some(exp) => exp,
+            None => continue,
+          };
+          for equiv in equivalences.iter() {
+            if let Some(method) = equiv.method.as_ref() {
+              if let Some(transformed) = method(&exp) {
+                unions.push((*id, Rewrite::Expression(transformed)));
+              }
+            }
+          }
+        }
+      }
+      for equiv in equivalences.iter() {
+        if equiv.method.is_some() { continue; }
+        for id in self.classes.keys() {
+          for subst in self.ematch(&equiv.before, *id, &HashMap::new()) {
+            if let Some(assumed) = self.assumed_conditions(equiv, &subst, &best, known) {
+              unions.push((*id, Rewrite::Pattern(&equiv.after, subst, assumed)));
+            }
+          }
+          if !equiv.forwards_only {
+            for subst in self.ematch(&equiv.after, *id, &HashMap::new()) {
+              if let Some(assumed) = self.assumed_conditions(equiv, &subst, &best, known) {
+                unions.push((*id, Rewrite::Pattern(&equiv.before, subst, assumed)));
+              }
+            }
+          }
+        }
+      }
+
+      let mut changed = false;
+      for (id, rewrite) in unions {
+        let (rewritten, assumed) = match rewrite {
+          Rewrite::Expression(e) => (Some(self.add_expr(&e)), Vec::new()),
+          Rewrite::Pattern(pattern, subst, assumed) => (self.instantiate(pattern, &subst), assumed),
+        };
+        if let Some(rewritten) = rewritten {
+          if self.union(id, rewritten) {
+            changed = true;
+            self.assumed.extend(assumed.into_iter().map(|condition| (id, condition)));
+          }
+        }
+        if self.node_count() > node_limit {
+          self.rebuild();
+          return SaturationStop::NodeLimit;
+        }
+      }
+      let node_count = self.node_count();
+      self.rebuild();
+      if !changed && node_count == self.node_count() {
+        return SaturationStop::Saturated;
+      }
+    }
+    SaturationStop::IterationLimit
+  }
+}
+
+// Whether b holds whenever a does, in the few obvious ways that rules end up assuming things twice.
+fn implies(a: &Condition, b: &Condition) -> bool {
+  match (a, b) {
+    // a != 0 implies a^n != 0
+    (Condition::NonZero(a), Condition::NonZero(Expression::Power(base, exponent))) =>
+      **base == *a && exponent.unwrap_constant().is_some(),
+    (a, Condition::Any(conditions)) => conditions.iter().any(|b| a == b || implies(a, b)),
+    _ => false,
+  }
+}
+
+enum Rewrite<'a> {
+  Expression(Expression),
+  // with the conditions assumed to match it
+  Pattern(&'a Expression, Substitution, Vec<Condition>),
+}
+
+const NODE_LIMIT: usize = 10_000;
+const ITERATION_LIMIT: usize = 30;
+
+/// The smallest expression in the e-graph, and how big the e-graph got.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaturationResult {
+  pub expression: Expression,
+  pub measure: i32,
+  pub stop_reason: SaturationStop,
+  /// What had to be assumed about the variables, which may be more than the expression needs.
+  pub assumptions: Vec<Condition>,
+  pub node_count: usize,
+  pub class_count: usize,
+  pub elapsed: Duration,
+}
+
+/// Like `measure::find_min_equivalent_expr`, but by equality saturation over an e-graph.
+pub fn find_min_equivalent_expr(e: Expression, rules: &Rules, known: &Assumptions, cost: &dyn CostFunction)
+                                -> SaturationResult {
+  let started = Instant::now();
+  let mut egraph = EGraph::new();
+  let root = egraph.add_expr(&e);
+  // there's no separate simplification pass, the simple rules are just more rules
+  let equivalences: Vec<&Equivalence> = rules.equivalences.iter().chain(rules.simple.iter()).collect();
+  let stop_reason = egraph.run(&equivalences, known, NODE_LIMIT, ITERATION_LIMIT);
+  let expression = egraph.extract(root, cost);
+  SaturationResult {
+    measure: cost.cost(&expression),
+    expression,
+    stop_reason,
+    assumptions: egraph.assumptions(root),
+    node_count: egraph.node_count(),
+    class_count: egraph.class_count(),
+    elapsed: started.elapsed(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cost::{PreferExpanded, PreferFactored};
+  use crate::parser::{parse, ParseError};
+
+  fn simplify(e: Expression, known: &Assumptions) -> SaturationResult {
+    find_min_equivalent_expr(e, &Rules::default(), known, &Weighted::default())
+  }
+
+  fn assert_min_equivalent(e: &str, min: &str) -> Result<(), ParseError> {
+    let e = parse(e)?;
+    let min = parse(min)?;
+    assert_eq!(simplify(e, &Assumptions::new()).expression, min);
+    Ok(())
+  }
+
+  #[test]
+  fn test_congruence() -> Result<(), ParseError> {
+    let mut egraph = EGraph::new();
+    let fa = egraph.add_expr(&parse("a*c")?);
+    let fb = egraph.add_expr(&parse("b*c")?);
+    let a = egraph.add_expr(&parse("a")?);
+    let b = egraph.add_expr(&parse("b")?);
+    assert_ne!(egraph.find(fa), egraph.find(fb));
+    egraph.union(a, b);
+    egraph.rebuild();
+    assert_eq!(egraph.find(fa), egraph.find(fb));
+    Ok(())
+  }
+
+  #[test]
+  fn test_hash_consing() -> Result<(), ParseError> {
+    let mut egraph = EGraph::new();
+    egraph.add_expr(&parse("(a+b)*(a+b)")?);
+    // a, b, a+b and the product
+    assert_eq!(egraph.node_count(), 4);
+    Ok(())
+  }
+
+  #[test]
+  fn test_constants() -> Result<(), ParseError> {
+    assert_min_equivalent("1+2^2*9/6-3", "4")
+  }
+
+  #[test]
+  fn test_fraction_cancel() -> Result<(), ParseError> {
+    assert_min_equivalent("(a/b)*(c*b/a)", "c")
+  }
+
+  #[test]
+  fn test_longer_factoring_and_cancellation() -> Result<(), ParseError> {
+    assert_min_equivalent("(a^2+2*a*b+b^2)/(a+b)", "a+b")
+  }
+
+  #[test]
+  fn test_assumptions() -> Result<(), ParseError> {
+    let result = simplify(parse("x/x+0*y")?, &Assumptions::new());
+    assert_eq!(result.expression, parse("1")?);
+    assert_eq!(result.assumptions, vec![Condition::NonZero(parse("x")?)]);
+    let mut known = Assumptions::new();
+    known.assume_str("x > 0").unwrap();
+    assert_eq!(simplify(parse("x/x")?, &known).assumptions, vec![]);
+    let result = simplify(parse("1+2^2*9/6-3")?, &Assumptions::new());
+    assert_eq!(result.assumptions, vec![]);
+    Ok(())
+  }
+
+  #[test]
+  fn test_cost() -> Result<(), ParseError> {
+    let e = parse("x*y+x*z")?;
+    let result = find_min_equivalent_expr(e, &Rules::default(), &Assumptions::new(), &PreferFactored);
+    assert_eq!(result.expression, parse("x*(y+z)")?);
+    assert_eq!(result.measure, PreferFactored.cost(&result.expression));
+    let result = find_min_equivalent_expr(parse("x*(y+z)")?, &Rules::default(), &Assumptions::new(), &PreferExpanded);
+    assert_eq!(result.measure, PreferExpanded.cost(&parse("x*y+x*z")?));
+    Ok(())
+  }
+
+  #[test]
+  fn test_difference_of_squares_cancellation() -> Result<(), ParseError> {
+    assert_min_equivalent("(a^2-b^2)/(a-b)", "a+b")
+  }
+}
@@ -0,0 +1,172 @@
+use crate::parser::{Expression, Function, Rational, rational_pow};
+use num_integer::Integer;
+use num_traits::{Signed, ToPrimitive, Zero};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Why an expression has no value for the given variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+  UnboundVariable(String),
+  DivisionByZero,
+  /// 0^0, or 0 to a negative power.
+  ZeroToNonPositivePower,
+  /// e.g. (-1)^(1/2), which isn't real.
+  NegativeBaseFractionalExponent,
+  /// e.g. ln(0) or sqrt(-1).
+  OutOfDomain(Function),
+  /// Exact evaluation only: the value is real, but not a rational that can be computed, e.g. sqrt(2) or 2^(10^9).
+  NotRational,
+  /// Floating point evaluation only: the value is too big for an f64.
+  Overflow,
+}
+
+impl fmt::Display for EvalError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      EvalError::UnboundVariable(v) => write!(f, "no value for variable '{}'", v),
+      EvalError::DivisionByZero => write!(f, "division by zero"),
+      EvalError::ZeroToNonPositivePower => write!(f, "0 to a power that isn't positive"),
+      EvalError::NegativeBaseFractionalExponent => write!(f, "negative number to a fractional power"),
+      EvalError::OutOfDomain(func) => write!(f, "argument of {} out of its domain", func.name()),
+      EvalError::NotRational => write!(f, "not an exact rational"),
+      EvalError::Overflow => write!(f, "too big for a floating point number"),
+    }
+  }
+}
+
+impl Expression {
+  /// The value of the expression, with each variable replaced by its value in env.
+  pub fn evaluate(&self, env: &HashMap<String, f64>) -> Result<f64, EvalError> {
+    let value = match self {
+      Expression::Constant(c) => c.to_f64().ok_or(EvalError::Overflow)?,
+      Expression::Variable(v) => *env.get(v).ok_or_else(|| EvalError::UnboundVariable(v.clone()))?,
+      Expression::Sum(a, b) => a.evaluate(env)? + b.evaluate(env)?,
+      Expression::Product(a, b) => a.evaluate(env)? * b.evaluate(env)?,
+      Expression::Difference(a, b) => a.evaluate(env)? - b.evaluate(env)?,
+      Expression::Quotient(a, b) => {
+        let (a, b) = (a.evaluate(env)?, b.evaluate(env)?);
+        if b == 0. { return Err(EvalError::DivisionByZero) }
+        a / b
+      },
+      Expression::Power(a, exponent) => {
+        let (a, b) = (a.evaluate(env)?, exponent.evaluate(env)?);
+        if a == 0. && b <= 0. { return Err(EvalError::ZeroToNonPositivePower) }
+        if a < 0. && b.fract() != 0. {
+          // a float can't tell (-8)^(1/3) from (-8)^(1/2), but a constant exponent can, like in evaluate_exact
+          match exponent.eval_const().and_then(|exponent| exponent.unwrap_constant()) {
+            Some(c) if c.denom().is_odd() => if c.numer().is_odd() { -(-a).powf(b) } else { (-a).powf(b) },
+            _ => return Err(EvalError::NegativeBaseFractionalExponent),
+          }
+        } else {
+          a.powf(b)
+        }
+      },
+      Expression::Apply(func, a) => {
+        let a = a.evaluate(env)?;
+        match func {
+          Function::Sin => a.sin(),
+          Function::Cos => a.cos(),
+          Function::Tan => a.tan(),
+          Function::Exp => a.exp(),
+          Function::Ln if a <= 0. => return Err(EvalError::OutOfDomain(*func)),
+          Function::Ln => a.ln(),
+          Function::Sqrt if a < 0. => return Err(EvalError::OutOfDomain(*func)),
+          Function::Sqrt => a.sqrt(),
+          Function::Abs => a.abs(),
+        }
+      },
+    };
+    if value.is_finite() { Ok(value) } else { Err(EvalError::Overflow) }
+  }
+
+  /// Like `evaluate`, but with exact rationals. Fails with NotRational where that isn't enough.
+  pub fn evaluate_exact(&self, env: &HashMap<String, Rational>) -> Result<Rational, EvalError> {
+    match self {
+      Expression::Constant(c) => Ok(c.clone()),
+      Expression::Variable(v) => env.get(v).cloned().ok_or_else(|| EvalError::UnboundVariable(v.clone())),
+      Expression::Sum(a, b) => Ok(a.evaluate_exact(env)? + b.evaluate_exact(env)?),
+      Expression::Product(a, b) => Ok(a.evaluate_exact(env)? * b.evaluate_exact(env)?),
+      Expression::Difference(a, b) => Ok(a.evaluate_exact(env)? - b.evaluate_exact(env)?),
+      Expression::Quotient(a, b) => {
+        let (a, b) = (a.evaluate_exact(env)?, b.evaluate_exact(env)?);
+        if b.is_zero() { return Err(EvalError::DivisionByZero) }
+        Ok(a / b)
+      },
+      Expression::Power(a, b) => {
+        let (a, b) = (a.evaluate_exact(env)?, b.evaluate_exact(env)?);
+        if a.is_zero() && !b.is_positive() { return Err(EvalError::ZeroToNonPositivePower) }
+        // odd roots of negative numbers are fine, e.g. (-8)^(1/3) = -2
+        if a.is_negative() && b.denom().is_even() { return Err(EvalError::NegativeBaseFractionalExponent) }
+        rational_pow(&a, &b).ok_or(EvalError::NotRational)
+      },
+      Expression::Apply(func, a) => {
+        let a = a.evaluate_exact(env)?;
+        match func {
+          Function::Ln if !a.is_positive() => Err(EvalError::OutOfDomain(*func)),
+          Function::Sqrt if a.is_negative() => Err(EvalError::OutOfDomain(*func)),
+          _ => func.eval_const(&a).ok_or(EvalError::NotRational),
+        }
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::{parse, ParseError};
+
+  fn env(values: &[(&str, f64)]) -> HashMap<String, f64> {
+    values.iter().map(|(v, x)| (v.to_string(), *x)).collect()
+  }
+
+  fn exact_env(values: &[(&str, i32, i32)]) -> HashMap<String, Rational> {
+    values.iter().map(|(v, n, d)| (v.to_string(), Rational::new((*n).into(), (*d).into()))).collect()
+  }
+
+  #[test]
+  fn test_evaluate() -> Result<(), ParseError> {
+    assert_eq!(parse("x^2+1")?.evaluate(&env(&[("x", 3.)])), Ok(10.));
+    assert_eq!(parse("sqrt(x)*abs(y)")?.evaluate(&env(&[("x", 4.), ("y", -1.5)])), Ok(3.));
+    assert_eq!(parse("x+y")?.evaluate(&env(&[("x", 1.)])), Err(EvalError::UnboundVariable("y".into())));
+    Ok(())
+  }
+
+  #[test]
+  fn test_evaluate_errors() -> Result<(), ParseError> {
+    let x = env(&[("x", 0.)]);
+    assert_eq!(parse("1/x")?.evaluate(&x), Err(EvalError::DivisionByZero));
+    assert_eq!(parse("x^0")?.evaluate(&x), Err(EvalError::ZeroToNonPositivePower));
+    assert_eq!(parse("ln(x)")?.evaluate(&x), Err(EvalError::OutOfDomain(Function::Ln)));
+    assert_eq!(parse("(x-2)^(1/2)")?.evaluate(&x), Err(EvalError::NegativeBaseFractionalExponent));
+    assert_eq!(parse("exp(1000)")?.evaluate(&x), Err(EvalError::Overflow));
+    Ok(())
+  }
+
+  #[test]
+  fn test_odd_roots() -> Result<(), ParseError> {
+    // the same as evaluate_exact for constant exponents
+    let x = env(&[("x", -8.)]);
+    assert_eq!(parse("x^(1/3)")?.evaluate(&x), Ok(-2.));
+    assert!((parse("x^(2/3)")?.evaluate(&x).unwrap() - 4.).abs() < 1e-12);
+    assert_eq!(parse("x^(1/x)")?.evaluate(&x), Err(EvalError::NegativeBaseFractionalExponent));
+    let exact = exact_env(&[("x", -8, 1)]);
+    assert_eq!(parse("x^(1/3)+2^(1/2)")?.evaluate_exact(&exact), Err(EvalError::NotRational));
+    assert!((parse("x^(1/3)+2^(1/2)")?.evaluate(&x).unwrap() - (2f64.sqrt() - 2.)).abs() < 1e-12);
+    Ok(())
+  }
+
+  #[test]
+  fn test_evaluate_exact() -> Result<(), ParseError> {
+    let x = exact_env(&[("x", 1, 2)]);
+    assert_eq!(parse("x^2+1/3")?.evaluate_exact(&x), Ok(Rational::new(7.into(), 12.into())));
+    assert_eq!(parse("(x-1)^(1/3)")?.evaluate_exact(&exact_env(&[("x", -7, 1)])),
+               Ok(Rational::from_integer((-2).into())));
+    assert_eq!(parse("(x-1)^(1/2)")?.evaluate_exact(&x), Err(EvalError::NegativeBaseFractionalExponent));
+    assert_eq!(parse("x^(1/2)")?.evaluate_exact(&x), Err(EvalError::NotRational));
+    assert_eq!(parse("(x-x)^0")?.evaluate_exact(&x), Err(EvalError::ZeroToNonPositivePower));
+    assert_eq!(parse("sqrt(x-1)")?.evaluate_exact(&x), Err(EvalError::OutOfDomain(Function::Sqrt)));
+    Ok(())
+  }
+}
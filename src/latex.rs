@@ -0,0 +1,606 @@
+use crate::c;
+use crate::parser::{deeper, nested_too_deep, Expression, Function, ParseError, Parsed, Rational, MAX_NESTING};
+use crate::transformation_graph::Derivation;
+use num_bigint::BigInt;
+use num_traits::{Pow, Signed, Zero};
+use std::ops::Range;
+
+// Greek letters are variables named after them, e.g. \alpha is the variable alpha.
+const GREEK: [&str; 36] = [
+  "alpha", "beta", "gamma", "delta", "epsilon", "varepsilon", "zeta", "eta", "theta", "vartheta", "iota", "kappa",
+  "lambda", "mu", "nu", "xi", "pi", "rho", "sigma", "tau", "upsilon", "phi", "varphi", "chi", "psi", "omega",
+  "Gamma", "Delta", "Theta", "Lambda", "Xi", "Pi", "Sigma", "Upsilon", "Phi", "Omega",
+];
+
+// Commands that only add space.
+const SPACING: [&str; 7] = [",", ";", ":", "!", " ", "quad", "qquad"];
+
+// How tightly each kind of expression binds. A fraction is typeset as a block, so it binds like a leaf,
+// except as the base of a power.
+fn precedence(e: &Expression) -> u8 {
+  match e {
+    Expression::Sum(_, _) | Expression::Difference(_, _) => 1,
+    Expression::Product(_, _) => 2,
+    Expression::Power(_, _) => 3,
+    Expression::Constant(_) | Expression::Variable(_) | Expression::Apply(_, _) | Expression::Quotient(_, _) => 4,
+  }
+}
+
+fn constant(c: &Rational) -> String {
+  if c.is_integer() {
+    c.to_string()
+  } else {
+    let sign = if c.is_negative() { "-" } else { "" };
+    format!("{}\\frac{{{}}}{{{}}}", sign, c.numer().abs(), c.denom())
+  }
+}
+
+fn variable(v: &str) -> String {
+  if v.chars().count() == 1 {
+    v.to_string()
+  } else if GREEK.contains(&v) {
+    format!("\\{}", v)
+  } else {
+    format!("\\mathit{{{}}}", v)
+  }
+}
+
+fn parenthesized(latex: String) -> String {
+  format!("\\left({}\\right)", latex)
+}
+
+// leftmost: whether the expression starts its group, where a leading - reads as a sign.
+fn render(e: &Expression, leftmost: bool) -> String {
+  let operand = |child: &Expression, right: bool, leftmost: bool| {
+    let parentheses = match child {
+      Expression::Constant(c) if c.is_negative() => !leftmost,
+      _ => precedence(child) < precedence(e) || (right && precedence(child) == precedence(e)),
+    };
+    if parentheses { parenthesized(render(child, true)) } else { render(child, leftmost) }
+  };
+  match e {
+    Expression::Constant(c) => constant(c),
+    Expression::Variable(v) => variable(v),
+    Expression::Sum(a, b) => format!("{} + {}", operand(a, false, leftmost), operand(b, true, false)),
+    Expression::Difference(a, b) => format!("{} - {}", operand(a, false, leftmost), operand(b, true, false)),
+    Expression::Product(a, b) => format!("{} \\cdot {}", operand(a, false, leftmost), operand(b, true, false)),
+    Expression::Quotient(a, b) => format!("\\frac{{{}}}{{{}}}", render(a, true), render(b, true)),
+    Expression::Power(a, b) => {
+      let base = match a.as_ref() {
+        Expression::Constant(c) if c.is_integer() && !c.is_negative() => render(a, true),
+        Expression::Variable(_) => render(a, true),
+        // sin(x)^2 is clear as it is
+        Expression::Apply(func, _) if *func != Function::Sqrt => render(a, true),
+        _ => parenthesized(render(a, true)),
+      };
+      format!("{}^{{{}}}", base, render(b, true))
+    },
+    Expression::Apply(func, a) => {
+      let a = render(a, true);
+      match func {
+        Function::Sqrt => format!("\\sqrt{{{}}}", a),
+        Function::Abs => format!("\\left|{}\\right|", a),
+        _ => format!("\\{}{}", func.name(), parenthesized(a)),
+      }
+    },
+  }
+}
+
+// For rule names and assumptions, which are plain text.
+fn text(s: &str) -> String {
+  let mut escaped = String::new();
+  for c in s.chars() {
+    match c {
+      '\\' => escaped.push_str("\\textbackslash{}"),
+      '^' => escaped.push_str("\\^{}"),
+      '~' => escaped.push_str("\\~{}"),
+      '{' | '}' | '_' | '#' | '$' | '%' | '&' => {
+        escaped.push('\\');
+        escaped.push(c);
+      },
+      _ => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+impl Expression {
+  /// The expression as LaTeX math, e.g. `\frac{a}{b} \cdot \sin\left(x\right)^{2}`.
+  pub fn to_latex(&self) -> String {
+    render(self, true)
+  }
+}
+
+/// The derivation as an align* environment, one step per line, each annotated with the rule it used.
+/// Expressions are sugared, as by `Expression::pretty`.
+pub fn derivation_to_latex(derivation: &Derivation) -> String {
+  let mut lines = vec![String::from("\\begin{align*}")];
+  let mut first = derivation.start.sugared().to_latex();
+  if derivation.steps.is_empty() {
+    lines.push(format!("{} \\\\", first));
+  }
+  for step in derivation.steps.iter() {
+    let mut note = format!("via \\texttt{{{}}}{}", text(&step.rule), if step.reverse { " reversed" } else { "" });
+    if !step.assumptions.is_empty() {
+      let assumptions: Vec<String> = step.assumptions.iter().map(|c| text(&c.to_string())).collect();
+      note.push_str(&format!(", assuming \\texttt{{{}}}", assumptions.join(", ")));
+    }
+    lines.push(format!("{} &= {} && \\text{{{}}} \\\\", first, step.exp.sugared().to_latex(), note));
+    first = String::new();
+  }
+  lines.push(String::from("\\end{align*}"));
+  lines.join("\n")
+}
+
+/// Parses the subset of LaTeX math that formulas in papers are mostly written in: `\frac{a}{b}`, `x^{2}`,
+/// `\sqrt{x}` and `\sqrt[3]{x}`, `\sin`, `\ln` etc., `\cdot` and `\times`, `\left( \right)`, `|x|`,
+/// Greek letters, subscripts like `x_{1}`, and implicit multiplication, so `2xy` is `2*x*y`.
+/// As usual in LaTeX, variables are single letters, or multi-letter ones written `\mathit{name}`.
+pub fn parse_latex(latex: &str) -> Result<Expression, ParseError> {
+  let mut parser = LatexParser { input: latex, position: 0, nesting: 0 };
+  let (e, _) = parser.expression()?;
+  parser.skip_space();
+  match parser.peek() {
+    None => Ok(e),
+    Some(_) => Err(parser.unexpected(&["an operator", "the end of the expression"])),
+  }
+}
+
+// A recursive descent parser, with the position of the next character to read.
+struct LatexParser<'a> {
+  input: &'a str,
+  position: usize,
+  // how many atoms the parser is inside of
+  nesting: usize,
+}
+
+impl<'a> LatexParser<'a> {
+  // The next character, or nothing at the end of the input.
+  fn next_span(&self) -> Range<usize> {
+    self.position..self.position + self.peek().map_or(0, char::len_utf8)
+  }
+
+  fn error(&self, msg: String) -> ParseError {
+    ParseError::new(self.next_span(), msg, &[])
+  }
+
+  fn unexpected(&self, expected: &[&str]) -> ParseError {
+    match self.peek() {
+      Some(c) => ParseError::new(self.next_span(), format!("unexpected '{}'", c), expected),
+      None => ParseError::new(self.next_span(), String::from("unexpected end of expression"), expected),
+    }
+  }
+
+  fn rest(&self) -> &'a str {
+    &self.input[self.position..]
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.rest().chars().next()
+  }
+
+  // The name of the command at the position, e.g. frac for \frac, or , for \,
+  fn peek_command(&self) -> Option<&'a str> {
+    let rest = self.rest().strip_prefix('\\')?;
+    let length = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+    match length {
+      0 => rest.chars().next().map(|c| &rest[..c.len_utf8()]),
+      _ => Some(&rest[..length]),
+    }
+  }
+
+  fn skip_space(&mut self) {
+    loop {
+      let rest = self.rest();
+      self.position += rest.len() - rest.trim_start().len();
+      match self.peek_command() {
+        Some(command) if SPACING.contains(&command) => self.position += 1 + command.len(),
+        _ => return,
+      }
+    }
+  }
+
+  fn eat(&mut self, c: char) -> bool {
+    self.skip_space();
+    if self.peek() == Some(c) {
+      self.position += c.len_utf8();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn eat_command(&mut self, command: &str) -> bool {
+    self.skip_space();
+    if self.peek_command() == Some(command) {
+      self.position += 1 + command.len();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn expect(&mut self, c: char) -> Result<(), ParseError> {
+    if self.eat(c) { Ok(()) } else { Err(self.unexpected(&[&format!("'{}'", c)])) }
+  }
+
+  // An operator joining lhs and rhs, where the operator was at span.
+  fn join(span: Range<usize>, (lhs, lhs_depth): Parsed, (rhs, rhs_depth): Parsed,
+          op: fn(Expression, Expression) -> Expression) -> Result<Parsed, ParseError> {
+    Ok((op(lhs, rhs), deeper(lhs_depth.max(rhs_depth), span)?))
+  }
+
+  fn expression(&mut self) -> Result<Parsed, ParseError> {
+    let mut e = self.term()?;
+    loop {
+      self.skip_space();
+      let span = self.next_span();
+      if self.eat('+') {
+        e = Self::join(span, e, self.term()?, |a, b| a + b)?;
+      } else if self.eat('-') {
+        e = Self::join(span, e, self.term()?, |a, b| a - b)?;
+      } else {
+        return Ok(e);
+      }
+    }
+  }
+
+  fn term(&mut self) -> Result<Parsed, ParseError> {
+    let mut e = self.signed()?;
+    loop {
+      self.skip_space();
+      let span = self.next_span();
+      if self.eat('*') || self.eat_command("cdot") || self.eat_command("times") {
+        e = Self::join(span, e, self.signed()?, |a, b| a * b)?;
+      } else if self.eat('/') || self.eat_command("div") {
+        e = Self::join(span, e, self.signed()?, |a, b| a / b)?;
+      } else if self.starts_factor() {
+        e = Self::join(span, e, self.power()?, |a, b| a * b)?;
+      } else {
+        return Ok(e);
+      }
+    }
+  }
+
+  // -x^2 is -(x^2), and -2 is a constant
+  fn signed(&mut self) -> Result<Parsed, ParseError> {
+    // the signs are counted rather than parsed recursively, so any number of them takes little of the stack
+    let mut minuses = Vec::new();
+    loop {
+      self.skip_space();
+      let span = self.next_span();
+      if self.eat('-') {
+        minuses.push(span);
+      } else if !self.eat('+') {
+        break;
+      }
+    }
+    let (mut e, mut depth) = self.power()?;
+    for span in minuses.into_iter().rev() {
+      e = match e {
+        Expression::Constant(c) => Expression::Constant(-c),
+        e => {
+          depth = deeper(depth, span)?;
+          c!(-1) * e
+        },
+      };
+    }
+    Ok((e, depth))
+  }
+
+  // Whether what follows is multiplied without an operator, like the y in 2xy.
+  fn starts_factor(&mut self) -> bool {
+    self.skip_space();
+    match self.peek() {
+      Some('\\') => !matches!(self.peek_command(), Some("cdot") | Some("times") | Some("div") | Some("right")),
+      Some(c) => c.is_ascii_alphanumeric() || c == '(' || c == '[' || c == '{',
+      None => false,
+    }
+  }
+
+  fn power(&mut self) -> Result<Parsed, ParseError> {
+    let base = self.atom()?;
+    self.superscript(base)
+  }
+
+  fn superscript(&mut self, base: Parsed) -> Result<Parsed, ParseError> {
+    self.skip_space();
+    let span = self.next_span();
+    if !self.eat('^') {
+      return Ok(base);
+    }
+    let exponent = self.argument()?;
+    self.skip_space();
+    if self.peek() == Some('^') {
+      return Err(self.error(String::from("double superscript, use braces like x^{a^{b}}")));
+    }
+    Self::join(span, base, exponent, |a, b| a ^ b)
+  }
+
+  // The argument of ^, \frac or \sqrt: a group in braces, or else a single digit, letter or command.
+  fn argument(&mut self) -> Result<Parsed, ParseError> {
+    self.skip_space();
+    match self.peek() {
+      Some(c) if c.is_ascii_digit() => {
+        self.position += 1;
+        Ok((c!(c.to_digit(10).unwrap() as i32), 1))
+      },
+      Some(c) if c.is_ascii_alphabetic() || c == '\\' || c == '{' => self.atom(),
+      _ => Err(self.unexpected(&["'{'", "a digit", "a letter", "a command"])),
+    }
+  }
+
+  // A function's argument: parenthesized, or else everything up to the next product, as in \sin x^2.
+  fn function_argument(&mut self) -> Result<Parsed, ParseError> {
+    self.skip_space();
+    match self.peek() {
+      Some('(') | Some('{') => self.atom(),
+      Some('\\') if self.peek_command() == Some("left") => self.atom(),
+      _ => self.power(),
+    }
+  }
+
+  // A subscript is part of the variable's name, so x_{1} is the variable x_1.
+  fn subscripted(&mut self, name: String) -> Result<Parsed, ParseError> {
+    if !self.eat('_') {
+      return Ok((Expression::Variable(name), 1));
+    }
+    let subscript = if self.eat('{') {
+      let subscript = self.word();
+      self.expect('}')?;
+      subscript
+    } else {
+      let rest = self.rest();
+      let length = rest.chars().next().filter(char::is_ascii_alphanumeric).map_or(0, char::len_utf8);
+      self.position += length;
+      &rest[..length]
+    };
+    if subscript.is_empty() {
+      return Err(self.unexpected(&["a subscript of letters and digits"]));
+    }
+    Ok((Expression::Variable(format!("{}_{}", name, subscript)), 1))
+  }
+
+  fn word(&mut self) -> &'a str {
+    self.skip_space();
+    let rest = self.rest();
+    let length = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+    self.position += length;
+    &rest[..length]
+  }
+
+  fn number(&mut self) -> Expression {
+    let rest = self.rest();
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let integer = digits(rest);
+    let decimals = rest[integer..].strip_prefix('.').map_or(0, digits);
+    let length = if decimals > 0 { integer + 1 + decimals } else { integer };
+    self.position += length;
+    // 1.25 is 125/10^2
+    let numer: BigInt = rest[..length].replace('.', "").parse().unwrap();
+    Expression::Constant(Rational::new(numer, BigInt::from(10).pow(decimals as u32)))
+  }
+
+  // Everything nested is nested in an atom, so this is where how deep the parser is gets counted.
+  fn atom(&mut self) -> Result<Parsed, ParseError> {
+    self.skip_space();
+    if self.nesting >= MAX_NESTING {
+      return Err(nested_too_deep(self.next_span()));
+    }
+    self.nesting += 1;
+    let parsed = self.unnested_atom();
+    self.nesting -= 1;
+    parsed
+  }
+
+  fn unnested_atom(&mut self) -> Result<Parsed, ParseError> {
+    let start = self.next_span();
+    let c = match self.peek() {
+      Some(c) => c,
+      None => return Err(self.unexpected(&["an expression"])),
+    };
+    if c.is_ascii_digit() {
+      return Ok((self.number(), 1));
+    }
+    if c.is_ascii_alphabetic() {
+      self.position += 1;
+      return self.subscripted(c.to_string());
+    }
+    for (open, close) in [('(', ')'), ('[', ']'), ('{', '}'), ('|', '|')].iter() {
+      if self.eat(*open) {
+        let e = self.expression()?;
+        self.expect(*close)?;
+        return if *open == '|' { Self::abs(start, e) } else { Ok(e) };
+      }
+    }
+    match self.peek_command() {
+      Some(command) => {
+        self.position += 1 + command.len();
+        self.command(command, start.start)
+      },
+      None => Err(self.unexpected(&["an expression"])),
+    }
+  }
+
+  // |e|, where the | was at span.
+  fn abs(span: Range<usize>, (e, depth): Parsed) -> Result<Parsed, ParseError> {
+    Ok((Function::Abs.apply(e), deeper(depth, span)?))
+  }
+
+  // What the command starting at start, whose name has just been read, stands for.
+  fn command(&mut self, command: &str, start: usize) -> Result<Parsed, ParseError> {
+    let span = start..self.position;
+    match command {
+      "left" => {
+        self.skip_space();
+        let (open, close) = match self.peek() {
+          Some('(') => ('(', ')'),
+          Some('[') => ('[', ']'),
+          Some('|') => ('|', '|'),
+          _ => return Err(self.unexpected(&["'('", "'['", "'|'"])),
+        };
+        self.position += 1;
+        let e = self.expression()?;
+        if !self.eat_command("right") {
+          return Err(self.unexpected(&["\\right"]));
+        }
+        self.expect(close)?;
+        if open == '|' { Self::abs(span, e) } else { Ok(e) }
+      },
+      "frac" | "dfrac" | "tfrac" => {
+        let numerator = self.argument()?;
+        let denominator = self.argument()?;
+        Self::join(span, numerator, denominator, |a, b| a / b)
+      },
+      "sqrt" => self.root(span),
+      "mathit" | "mathrm" => {
+        self.expect('{')?;
+        let name = self.word();
+        if name.is_empty() {
+          return Err(self.unexpected(&["a variable name"]));
+        }
+        self.expect('}')?;
+        self.subscripted(name.to_string())
+      },
+      _ => match Function::from_name(command).filter(|func| *func != Function::Sqrt && *func != Function::Abs) {
+        // \sin^2 x is sin(x)^2
+        Some(func) => {
+          let exponent = if self.eat('^') { Some(self.argument()?) } else { None };
+          let (argument, depth) = self.function_argument()?;
+          let applied = (func.apply(argument), deeper(depth, span.clone())?);
+          match exponent {
+            Some(exponent) => Self::join(span, applied, exponent, |a, b| a ^ b),
+            None => Ok(applied),
+          }
+        },
+        None if GREEK.contains(&command) => self.subscripted(command.to_string()),
+        None => Err(ParseError::new(span, format!("unknown command '\\{}'", command), &[])),
+      },
+    }
+  }
+
+  // \sqrt{x}, or \sqrt[n]{x}, which is x^(1/n), with the \sqrt at span.
+  fn root(&mut self, span: Range<usize>) -> Result<Parsed, ParseError> {
+    let index = if self.eat('[') {
+      let start = self.position;
+      let index = self.expression()?;
+      if index.0.unwrap_constant().is_some_and(|n| n.is_zero()) {
+        return Err(ParseError::new(start..self.position, String::from("the index of a root can't be 0"), &[]));
+      }
+      self.expect(']')?;
+      Some(index)
+    } else {
+      None
+    };
+    let (radicand, depth) = self.argument()?;
+    match index {
+      Some((Expression::Constant(n), _)) if n != Rational::from_integer(2.into()) =>
+        Self::join(span, (radicand, depth), (Expression::Constant(n.recip()), 1), |a, b| a ^ b),
+      None | Some((Expression::Constant(_), _)) => Ok((Function::Sqrt.apply(radicand), deeper(depth, span)?)),
+      Some((index, index_depth)) => {
+        let exponent = (c!(1) / index, deeper(index_depth, span.clone())?);
+        Self::join(span, (radicand, depth), exponent, |a, b| a ^ b)
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::{parse, ParseError};
+  use crate::tree_transform::Step;
+
+  fn assert_latex(e: &str, latex: &str) -> Result<(), ParseError> {
+    assert_eq!(parse(e)?.to_latex(), latex);
+    Ok(())
+  }
+
+  #[test]
+  fn test_latex() -> Result<(), ParseError> {
+    assert_latex("a+b*c", "a + b \\cdot c")?;
+    assert_latex("(a+b)*c", "\\left(a + b\\right) \\cdot c")?;
+    assert_latex("(a+1)/(b^2)", "\\frac{a + 1}{b^{2}}")?;
+    assert_latex("(a/b)^2", "\\left(\\frac{a}{b}\\right)^{2}")?;
+    assert_latex("x^(a+b)", "x^{a + b}")?;
+    assert_latex("-2*x+(-3)*(1/2)", "-2 \\cdot x + \\left(-3\\right) \\cdot \\frac{1}{2}")?;
+    assert_latex("sin(x)^2+sqrt(abs(y))", "\\sin\\left(x\\right)^{2} + \\sqrt{\\left|y\\right|}")?;
+    assert_latex("alpha*ln(rate)", "\\alpha \\cdot \\ln\\left(\\mathit{rate}\\right)")
+  }
+
+  #[test]
+  fn test_derivation_to_latex() -> Result<(), ParseError> {
+    let derivation = Derivation {
+      start: parse("x/x")?,
+      steps: vec![Step {
+        exp: parse("1")?,
+        rule: String::from("a/a = 1 if a != 0"),
+        reverse: false,
+        position: vec![],
+        assumptions: vec![crate::condition::Condition::NonZero(parse("x")?)],
+      }],
+    };
+    assert_eq!(derivation_to_latex(&derivation), "\\begin{align*}\n\
+      \\frac{x}{x} &= 1 && \\text{via \\texttt{a/a = 1 if a != 0}, assuming \\texttt{x != 0}} \\\\\n\
+      \\end{align*}");
+    Ok(())
+  }
+
+  fn assert_parses(latex: &str, e: &str) -> Result<(), ParseError> {
+    assert_eq!(parse_latex(latex)?, parse(e)?, "parsing {}", latex);
+    Ok(())
+  }
+
+  #[test]
+  fn test_parse_latex() -> Result<(), ParseError> {
+    assert_parses("\\frac{a+1}{b^{2}}", "(a+1)/b^2")?;
+    assert_parses("\\frac12 + x^2", "1/2+x^2")?;
+    assert_parses("\\left( a + b \\right) \\cdot c", "(a+b)*c")?;
+    assert_parses("2xy", "2*x*y")?;
+    assert_parses("3(a+b)(a-b)", "3*(a+b)*(a-b)")?;
+    assert_parses("\\sqrt{x^2+1} \\times \\sqrt[3]{y}", "sqrt(x^2+1)*y^[1/3]")?;
+    assert_parses("\\sin^2 x + \\cos\\left(x\\right)^{2}", "sin(x)^2+cos(x)^2")?;
+    assert_parses("\\ln x^2 - |y|", "ln(x^2)-abs(y)")?;
+    assert_parses("-x^2 + -3", "(-1)*x^2+(-3)")?;
+    assert_parses("\\alpha_{1} \\, \\beta + 1.25", "alpha_1*beta+[5/4]")?;
+    assert_parses("\\mathit{rate} \\div \\mathrm{k}_2", "rate/k_2")
+  }
+
+  #[test]
+  fn test_parse_latex_errors() {
+    let error = |latex: &str| parse_latex(latex).unwrap_err().to_string();
+    assert_eq!(error("\\frac{a}{b"), "Parse Error at position 10: unexpected end of expression, expected '}'");
+    assert_eq!(error("x + \\foo"), "Parse Error at position 4: unknown command '\\foo'");
+    assert_eq!(error("x^2^3"), "Parse Error at position 3: double superscript, use braces like x^{a^{b}}");
+    assert_eq!(error("\\left(x"), "Parse Error at position 7: unexpected end of expression, expected \\right");
+    assert_eq!(error("a +"), "Parse Error at position 3: unexpected end of expression, expected an expression");
+    assert_eq!(error("\\sqrt[0]{4}"), "Parse Error at position 6: the index of a root can't be 0");
+  }
+
+  #[test]
+  fn test_parse_latex_too_deep() {
+    let error = |latex: &str| parse_latex(latex).unwrap_err().to_string();
+    let nesting = "Parse Error at position 128: parentheses, functions and operators are nested more than 128 deep";
+    assert_eq!(error(&format!("{}x{}", "(".repeat(2000), ")".repeat(2000))), nesting);
+    assert_eq!(error(&format!("{}x", "\\sin ".repeat(2000)).replace(' ', "")), "Parse Error at position 512: \
+      parentheses, functions and operators are nested more than 128 deep");
+    assert_eq!(error(&"\\left(".repeat(2000)), nesting.replace("128:", "768:"));
+    let deep = "Parse Error at position 1999: expression is more than 1000 operations deep";
+    // signs are applied from the inside out
+    assert_eq!(error(&format!("{}x", "-".repeat(300_000))), deep.replace("1999", "299000"));
+    assert_eq!(error(&vec!["x"; 100_000].join("+")), deep);
+    assert!(parse_latex(&format!("{}x{}", "\\sqrt{".repeat(60), "}".repeat(60))).is_ok());
+    assert!(parse_latex(&format!("{}x{}", "x^{".repeat(120), "}".repeat(120))).is_ok());
+    assert!(parse_latex(&format!("{}x{}", "\\left(".repeat(120), "\\right)".repeat(120))).is_ok());
+    assert!(parse_latex(&format!("{}x{}", "\\frac{".repeat(60), "}{y}".repeat(60))).is_ok());
+    assert!(parse_latex(&vec!["x"; 1000].join("+")).is_ok());
+  }
+
+  #[test]
+  fn test_latex_round_trips() -> Result<(), ParseError> {
+    for e in ["a+b*c-(d-e)", "(a+b)^(c*d)", "(-3)*x^2/(1+y)", "sin(x)^2+sqrt(abs(alpha))", "x*(y*z)", "ln(theta_1)"] {
+      assert_eq!(parse_latex(&parse(e)?.to_latex())?, parse(e)?);
+    }
+    Ok(())
+  }
+}
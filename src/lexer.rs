@@ -0,0 +1,106 @@
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<'a> {
+  /// Digits, without a sign.
+  Number(&'a str),
+  /// A variable or function name, like `x_1` or `sin`.
+  Identifier(&'a str),
+  Plus,
+  Minus,
+  Star,
+  Slash,
+  Caret,
+  LeftParen,
+  RightParen,
+  /// Brackets only go around fraction constants, like [1/2].
+  LeftBracket,
+  RightBracket,
+  Comma,
+  /// A character that can't start a token. The parser reports it, where it knows what was expected instead.
+  Unknown(char),
+}
+
+/// A token, and the bytes of the input it was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lexeme<'a> {
+  pub token: Token<'a>,
+  pub span: Range<usize>,
+}
+
+/// Splits the input into tokens, skipping whitespace and comments, which run from `#` to the end of the line.
+/// Only looks at each byte once, so it's fast enough for large batch files.
+pub fn tokenize(input: &str) -> Vec<Lexeme<'_>> {
+  let bytes = input.as_bytes();
+  let mut lexemes = Vec::new();
+  let mut position = 0;
+  // runs while the bytes match, e.g. the rest of a number
+  let run = |start: usize, matches: fn(u8) -> bool| {
+    start + bytes[start..].iter().position(|b| !matches(*b)).unwrap_or(bytes.len() - start)
+  };
+  while position < bytes.len() {
+    let start = position;
+    let token = match bytes[position] {
+      b if b.is_ascii_whitespace() => {
+        position = run(position, |b| b.is_ascii_whitespace());
+        continue;
+      },
+      b'#' => {
+        position = run(position, |b| b != b'\n');
+        continue;
+      },
+      b if b.is_ascii_digit() => {
+        position = run(position, |b| b.is_ascii_digit());
+        Token::Number(&input[start..position])
+      },
+      b'+' => Token::Plus,
+      b'-' => Token::Minus,
+      b'*' => Token::Star,
+      b'/' => Token::Slash,
+      b'^' => Token::Caret,
+      b'(' => Token::LeftParen,
+      b')' => Token::RightParen,
+      b'[' => Token::LeftBracket,
+      b']' => Token::RightBracket,
+      b',' => Token::Comma,
+      _ => {
+        let rest = &input[start..];
+        let length = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        if length > 0 {
+          position += length;
+          Token::Identifier(&rest[..length])
+        } else {
+          let c = rest.chars().next().unwrap();
+          position += c.len_utf8();
+          Token::Unknown(c)
+        }
+      },
+    };
+    if position == start {
+      position += 1;
+    }
+    lexemes.push(Lexeme{token, span: start..position});
+  }
+  lexemes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_tokenize() {
+    let tokens: Vec<Token> = tokenize("sin(x_1) + 12*y^(-3) # a comment\n, [$").into_iter().map(|l| l.token).collect();
+    assert_eq!(tokens, vec![
+      Token::Identifier("sin"), Token::LeftParen, Token::Identifier("x_1"), Token::RightParen, Token::Plus,
+      Token::Number("12"), Token::Star, Token::Identifier("y"), Token::Caret, Token::LeftParen, Token::Minus,
+      Token::Number("3"), Token::RightParen, Token::Comma, Token::LeftBracket, Token::Unknown('$'),
+    ]);
+  }
+
+  #[test]
+  fn test_spans() {
+    let spans: Vec<Range<usize>> = tokenize(" ab  +\tλ").into_iter().map(|l| l.span).collect();
+    assert_eq!(spans, vec![1..3, 5..6, 7..9]);
+  }
+}
@@ -0,0 +1,39 @@
+//! Simplifies expressions and proves them equal, by searching the rewrites a set of rules allows.
+//!
+//! The main entry points are `parse`, and `Simplifier` for simplifying and proving.
+
+mod parser;
+mod lexer;
+mod assumptions;
+mod condition;
+mod tree_transform;
+mod transformation_graph;
+mod cost;
+mod measure;
+mod egraph;
+mod prove;
+mod rules;
+mod soundness;
+mod evaluate;
+mod derivative;
+mod substitute;
+mod pretty;
+mod latex;
+mod simplifier;
+
+pub use assumptions::{Assumptions, Domain};
+pub use condition::Condition;
+pub use evaluate::EvalError;
+pub use parser::{parse, parse_bindings, parse_with, Expression, Function, Identifiers, ParseError, ParseOptions,
+                 Rational};
+pub use prove::Proof;
+pub use rules::{RuleError, Rules};
+pub use cost::{CostFunction, Depth, NodeCount, OperationCount, PreferExpanded, PreferFactored, Weighted};
+pub use egraph::{SaturationResult, SaturationStop};
+pub use measure::{measure, CancellationToken, Heuristic, Observer, SearchConfig, Silent, SimplifyResult, StopReason,
+                  Strategy};
+pub use latex::{derivation_to_latex, parse_latex};
+pub use simplifier::Simplifier;
+pub use soundness::{check_derivation, check_equivalence, Counterexample, SAMPLES};
+pub use transformation_graph::{Derivation, Graph};
+pub use tree_transform::{Equivalence, Step};
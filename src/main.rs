@@ -1,23 +1,425 @@
-mod parser;
-mod tree_transform;
-mod transformation_graph;
-mod measure;
-
+use num_bigint::BigInt;
+use num_traits::{Pow, ToPrimitive};
+use symbolic::{check_derivation, check_equivalence, derivation_to_latex, parse_bindings, parse_latex, parse_with,
+               SAMPLES};
+use symbolic::{Assumptions, CostFunction, Depth, EvalError, Expression, Graph, Identifiers, NodeCount, Observer,
+               OperationCount, ParseError, ParseOptions, PreferExpanded, PreferFactored, Proof, Rational, Rules,
+               SearchConfig, Simplifier, SimplifyResult, StopReason, Strategy, Weighted};
+use std::collections::HashMap;
+use std::env;
 use std::io;
-use std::time::Instant;
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// How results are printed, chosen with `--format text|latex`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+  Text,
+  Latex,
+}
+
+// How input expressions are written, chosen with `--input text|latex`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Input {
+  Text,
+  Latex,
+}
+
+impl Input {
+  // What separates an expression from its substitutions in the REPL. | is an absolute value in LaTeX.
+  fn separator(self) -> &'static str {
+    match self {
+      Input::Text => "|",
+      Input::Latex => " where ",
+    }
+  }
+}
+
+type Parser<'a> = &'a dyn Fn(&str) -> Result<Expression, ParseError>;
+
+// Removes the flag from args, and returns whether it was there.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+  let len = args.len();
+  args.retain(|arg| arg != name);
+  args.len() != len
+}
+
+// Removes `name value` from args, and returns the value.
+fn take_option(args: &mut Vec<String>, name: &str, usage: &str) -> Option<String> {
+  let i = args.iter().position(|arg| arg == name)?;
+  let value = args.get(i + 1).cloned().unwrap_or_else(|| {
+    eprintln!("usage: {} {}", name, usage);
+    process::exit(2);
+  });
+  args.drain(i..i + 2);
+  Some(value)
+}
+
+// Like `take_option`, for a number.
+fn take_number<T: FromStr>(args: &mut Vec<String>, name: &str) -> Option<T> {
+  let value = take_option(args, name, "<number>")?;
+  Some(value.parse().unwrap_or_else(|_| {
+    eprintln!("{} must be a number, got '{}'", name, value);
+    process::exit(2);
+  }))
+}
 
 fn main() {
-  loop {
-    println!("Enter a mathematical expression");
-    let mut expr = String::new();
+  let mut args: Vec<String> = env::args().collect();
+  // `--rules path` replaces the default rules with the ones in a rule file
+  let rules = match take_option(&mut args, "--rules", "<path to rule file>") {
+    Some(path) => Rules::load(&path).unwrap_or_else(|err| {
+      eprintln!("{}", err);
+      process::exit(2);
+    }),
+    None => Rules::default(),
+  };
+  // `--format latex` prints expressions and derivations as LaTeX
+  let format = match take_option(&mut args, "--format", "text|latex").as_deref() {
+    None | Some("text") => Format::Text,
+    Some("latex") => Format::Latex,
+    Some(other) => {
+      eprintln!("unknown format '{}', expected text or latex", other);
+      process::exit(2);
+    },
+  };
+  // `--implicit-multiplication` reads 2x as 2*x, and `--single-letter-variables` reads xy as x*y with it
+  let options = ParseOptions {
+    implicit_multiplication: take_flag(&mut args, "--implicit-multiplication"),
+    identifiers: match take_flag(&mut args, "--single-letter-variables") {
+      true => Identifiers::SingleLetters,
+      false => Identifiers::Words,
+    },
+  };
+  let parse_text = |e: &str| parse_with(e, &options);
+  // `--input latex` reads expressions written in LaTeX, e.g. pasted from a paper
+  let input = match take_option(&mut args, "--input", "text|latex").as_deref() {
+    None | Some("text") => Input::Text,
+    Some("latex") => Input::Latex,
+    Some(other) => {
+      eprintln!("unknown input '{}', expected text or latex", other);
+      process::exit(2);
+    },
+  };
+  let parse: Parser = match input {
+    Input::Text => &parse_text,
+    Input::Latex => &parse_latex,
+  };
+  if args.get(1).map(String::as_str) == Some("check-rules") {
+    return check_rules(&rules);
+  }
+  // `--search best-first` rewrites the cheapest expressions first, and `--beam-width n` keeps only n of them waiting
+  let beam_width = take_number(&mut args, "--beam-width");
+  if beam_width == Some(0) {
+    eprintln!("--beam-width must be at least 1");
+    process::exit(2);
+  }
+  let strategy = match take_option(&mut args, "--search", "breadth-first|best-first").as_deref() {
+    None if beam_width.is_some() => Strategy::BestFirst { heuristic: None, beam_width },
+    Some("breadth-first") if beam_width.is_some() => {
+      eprintln!("--beam-width only applies to --search best-first");
+      process::exit(2);
+    },
+    None | Some("breadth-first") => Strategy::BreadthFirst,
+    Some("best-first") => Strategy::BestFirst { heuristic: None, beam_width },
+    Some(other) => {
+      eprintln!("unknown search '{}', expected breadth-first or best-first", other);
+      process::exit(2);
+    },
+  };
+  // `--max-nodes n`, `--max-depth n` and `--timeout seconds` stop the search early, with the best found so far
+  let config = SearchConfig {
+    strategy,
+    max_nodes: take_number(&mut args, "--max-nodes"),
+    max_depth: take_number(&mut args, "--max-depth"),
+    timeout: take_number(&mut args, "--timeout").map(|seconds: f64| {
+      Duration::try_from_secs_f64(seconds).unwrap_or_else(|_| {
+        eprintln!("--timeout must be a number of seconds, got '{}'", seconds);
+        process::exit(2);
+      })
+    }),
+    ..SearchConfig::default()
+  };
+  // `--cost factored` prefers products of sums, and so on
+  let costs = "measure|nodes|operations|depth|expanded|factored";
+  let cost: Box<dyn CostFunction> = match take_option(&mut args, "--cost", costs).as_deref() {
+    None | Some("measure") => Box::new(Weighted::default()),
+    Some("nodes") => Box::new(NodeCount),
+    Some("operations") => Box::new(OperationCount),
+    Some("depth") => Box::new(Depth),
+    Some("expanded") => Box::new(PreferExpanded),
+    Some("factored") => Box::new(PreferFactored),
+    Some(other) => {
+      eprintln!("unknown cost '{}', expected one of {}", other, costs.replace('|', ", "));
+      process::exit(2);
+    },
+  };
+  // The REPL simplifies by equality saturation, which gets further than searching the transformation graph but
+  // can't say how it got there. `--derivation` searches the graph instead, to print how each result was derived.
+  let derivation = take_flag(&mut args, "--derivation");
+  // `--egraph` asks for equality saturation explicitly
+  let egraph = take_flag(&mut args, "--egraph");
+  let in_repl = !matches!(args.get(1).map(String::as_str), Some("prove" | "eval" | "diff"));
+  if egraph && !in_repl {
+    eprintln!("--egraph only applies to simplifying in the REPL");
+    process::exit(2);
+  }
+  if derivation && !in_repl {
+    eprintln!("--derivation only applies to simplifying in the REPL");
+    process::exit(2);
+  }
+  if egraph && derivation {
+    eprintln!("--egraph can't be used with --derivation, equality saturation can't say how it got its result");
+    process::exit(2);
+  }
+  let limited = config.max_nodes.is_some() || config.max_depth.is_some() || config.timeout.is_some() ||
+    matches!(config.strategy, Strategy::BestFirst { .. });
+  if in_repl && !derivation && limited {
+    eprintln!("--max-nodes, --max-depth, --timeout, --search and --beam-width only apply with --derivation");
+    process::exit(2);
+  }
+  // proofs search both ways breadth first, and stop at the node and time budgets only
+  if args.get(1).map(String::as_str) == Some("prove") {
+    if config.max_depth.is_some() {
+      eprintln!("--max-depth doesn't apply to prove");
+      process::exit(2);
+    }
+    if let Strategy::BestFirst { .. } = config.strategy {
+      eprintln!("--search best-first and --beam-width don't apply to prove");
+      process::exit(2);
+    }
+  }
+  let simplifier = Simplifier::new().rules(rules).cost(cost).search(config);
+  match args.get(1).map(String::as_str) {
+    Some("prove") => prove(&args[2..], &simplifier, parse, format),
+    Some("eval") => eval(&args[2..], parse),
+    Some("diff") => diff(&args[2..], &simplifier, parse, format),
+    _ => repl(simplifier, derivation, input, parse, format),
+  }
+}
 
-    io::stdin()
-      .read_line(&mut expr)
-      .expect("Failed to read expression");
+// Prints how the search is going, and the whole graph at the end.
+struct PrintProgress;
 
+impl Observer for PrintProgress {
+  fn started(&mut self, start: &Expression) {
+    println!("Parsed expression: {}", start);
+  }
+
+  fn reached_depth(&mut self, depth: usize, graph: &Graph) {
+    println!("Reached depth {} of transformations, with graph size {}", depth, graph.size());
+  }
+
+  fn finished(&mut self, graph: &Graph, result: &SimplifyResult) {
+    println!("Graph:\n{}", graph);
+    println!("{} with measure {} is distance {} away from {} ({} expressions, {} expanded, {} rewrites)",
+             result.expression, result.measure, result.depth, result.derivation.start, result.graph_size,
+             result.expanded, result.edges);
+    if result.stop_reason != StopReason::Exhausted {
+      println!("Stopped early: {:?}", result.stop_reason);
+    }
+  }
+}
+
+fn repl(mut simplifier: Simplifier, derivation: bool, input: Input, parse: Parser, format: Format) {
+  let mut known = Assumptions::new();
+  let separator = input.separator();
+  loop {
+    println!("Enter a mathematical expression, optionally with substitutions like 'x^2+y {} x = a+b', \
+              or an assumption like 'assume x > 0'", separator.trim());
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+      // end of input
+      Ok(0) => return,
+      Ok(_) => (),
+      Err(err) => {
+        eprintln!("Failed to read expression: {}", err);
+        return;
+      },
+    }
+    let line = line.trim_end();
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    if let Some(assumption) = line.trim().strip_prefix("assume ") {
+      match known.assume_str(assumption) {
+        Ok(()) => {
+          println!("Assuming {}", known);
+          simplifier = simplifier.assumptions(known.clone());
+        },
+        Err(err) => println!("{}", err),
+      }
+      continue;
+    }
     let now = Instant::now();
-    let root_exp = parser::parse(expr.trim()).unwrap();
-    measure::find_min_equivalent_expr(root_exp);
+    // errors are shifted to point into the whole line
+    let (exp, bindings) = match line.split_once(separator) {
+      Some((exp, bindings)) => (exp, Some(bindings)),
+      None => (line, None),
+    };
+    let parsed = parse(exp.trim()).map_err(|err| err.shifted(exp.len() - exp.trim_start().len()))
+      .and_then(|exp| match bindings {
+        Some(bindings) => parse_bindings(bindings, parse).map(|bindings| exp.substitute(&bindings))
+          .map_err(|err| err.shifted(line.len() - bindings.len())),
+        None => Ok(exp),
+      });
+    let root_exp = match parsed {
+      Ok(exp) => exp,
+      Err(err) => {
+        println!("{}", err.diagnostic(line));
+        continue;
+      },
+    };
+    let (min, assumptions) = if derivation {
+      let result = simplifier.simplify_observed(&root_exp, &mut PrintProgress);
+      let derivation = result.derivation;
+      match format {
+        Format::Text => println!("Derivation:\n{}", derivation),
+        Format::Latex => println!("Derivation:\n{}", derivation_to_latex(&derivation)),
+      }
+      if let Err(counterexample) = check_derivation(&derivation, &known, SAMPLES) {
+        println!("Warning: the derivation is wrong, a rule must be unsound. {}", counterexample);
+      }
+      (result.expression, derivation.assumptions())
+    } else {
+      let result = simplifier.simplify_by_egraph(&root_exp);
+      println!("{} with measure {} is equivalent to {} ({:?} with {} e-nodes in {} e-classes)",
+               result.expression, result.measure, root_exp, result.stop_reason, result.node_count, result.class_count);
+      (result.expression, result.assumptions)
+    };
+    if !assumptions.is_empty() {
+      let assumptions: Vec<String> = assumptions.iter().map(|c| c.to_string()).collect();
+      println!("Assuming {}", assumptions.join(", "));
+    }
+    match format {
+      Format::Text => println!("Result: {}", min.pretty()),
+      Format::Latex => println!("Result: {}", min.sugared().to_latex()),
+    }
     println!("Elapsed time {}s", now.elapsed().as_secs());
   }
 }
+
+// usage: symbolic [--max-nodes n] [--timeout seconds] prove <lhs> <rhs>
+fn prove(args: &[String], simplifier: &Simplifier, parse: Parser, format: Format) {
+  if args.len() != 2 {
+    eprintln!("usage: symbolic [--max-nodes n] [--timeout seconds] prove <lhs> <rhs>");
+    process::exit(2);
+  }
+  let parse_or_exit = |e: &str| parse(e).unwrap_or_else(|err| {
+    eprintln!("{}", err.diagnostic(e));
+    process::exit(2);
+  });
+  let lhs = parse_or_exit(&args[0]);
+  let rhs = parse_or_exit(&args[1]);
+  let now = Instant::now();
+  let proof = simplifier.prove(&lhs, &rhs);
+  match (&proof, format) {
+    (Proof::Proven(derivation), Format::Latex) =>
+      println!("Proven:\n{}", derivation_to_latex(derivation)),
+    _ => println!("{}", proof),
+  }
+  println!("Elapsed time {}s", now.elapsed().as_secs());
+  if let Proof::NotProven { .. } = proof {
+    process::exit(1);
+  }
+}
+
+// usage: symbolic check-rules [--rules path]
+// Evaluates both sides of every rule at random points, and exits with 1 if any of them disagree.
+fn check_rules(rules: &Rules) {
+  let mut unsound = 0;
+  for equivalence in rules.equivalences.iter().chain(rules.simple.iter()) {
+    match check_equivalence(equivalence, SAMPLES) {
+      Ok(0) => println!("unchecked: {}", equivalence),
+      Ok(checked) => println!("ok at {} points: {}", checked, equivalence),
+      Err(counterexample) => {
+        println!("UNSOUND: {}", counterexample);
+        unsound += 1;
+      },
+    }
+  }
+  if unsound > 0 {
+    println!("{} unsound rules", unsound);
+    process::exit(1);
+  }
+}
+
+// A decimal like 0.5 or -1.25, exactly, since the expression parser has no decimals.
+fn decimal(value: &str) -> Option<Rational> {
+  let (integer, fraction) = value.trim().split_once('.')?;
+  let digits = integer.strip_prefix('-').unwrap_or(integer);
+  if !(digits.chars().all(|c| c.is_ascii_digit()) && fraction.chars().all(|c| c.is_ascii_digit())) ||
+     fraction.is_empty() {
+    return None;
+  }
+  let numer: BigInt = format!("{}{}", integer, fraction).parse().ok()?;
+  Some(Rational::new(numer, BigInt::from(10).pow(fraction.len())))
+}
+
+// usage: symbolic eval <expression> [variable=value ...]
+// Prints the exact value if it is rational, and otherwise a floating point approximation.
+// Values are decimals like x=0.5, or expressions like x=1/3.
+fn eval(args: &[String], parse: Parser) {
+  if args.is_empty() {
+    eprintln!("usage: symbolic eval <expression> [variable=value ...], with values like x=0.5 or x=1/3");
+    process::exit(2);
+  }
+  let parse_or_exit = |e: &str| parse(e).unwrap_or_else(|err| {
+    eprintln!("{}", err.diagnostic(e));
+    process::exit(2);
+  });
+  let exp = parse_or_exit(&args[0]);
+  let mut env = HashMap::new();
+  for binding in args[1..].iter() {
+    let (variable, value) = binding.split_once('=').unwrap_or_else(|| {
+      eprintln!("expected variable=value, got '{}'", binding);
+      process::exit(2);
+    });
+    // values can be expressions too, e.g. x=1/3
+    let value = decimal(value).map_or_else(|| parse_or_exit(value).evaluate_exact(&HashMap::new()), Ok)
+      .unwrap_or_else(|err| {
+        eprintln!("value of {} must be an exact number: {}", variable, err);
+        process::exit(2);
+      });
+    env.insert(variable.to_string(), value);
+  }
+  let result = match exp.evaluate_exact(&env) {
+    Ok(value) if value.is_integer() => Ok(value.to_string()),
+    Ok(value) => Ok(format!("{} = {}", value, value.to_f64().unwrap_or(f64::NAN))),
+    Err(EvalError::NotRational) => {
+      let env = env.iter().map(|(v, x)| (v.clone(), x.to_f64().unwrap_or(f64::NAN))).collect();
+      exp.evaluate(&env).map(|value| value.to_string())
+    },
+    Err(err) => Err(err),
+  };
+  match result {
+    Ok(value) => println!("{}", value),
+    Err(err) => {
+      println!("undefined: {}", err);
+      process::exit(1);
+    },
+  }
+}
+
+// usage: symbolic diff <expression> [variable]
+fn diff(args: &[String], simplifier: &Simplifier, parse: Parser, format: Format) {
+  if args.is_empty() {
+    eprintln!("usage: symbolic diff <expression> [variable, x by default]");
+    process::exit(2);
+  }
+  let exp = parse(&args[0]).unwrap_or_else(|err| {
+    eprintln!("{}", err.diagnostic(&args[0]));
+    process::exit(2);
+  });
+  let var = args.get(1).map(String::as_str).unwrap_or("x");
+  let now = Instant::now();
+  let derivative = simplifier.derivative(&exp, var);
+  match format {
+    Format::Text => println!("d/d{} {} = {}", var, exp, derivative),
+    Format::Latex => println!("\\frac{{d}}{{d{}}} \\left({}\\right) = {}",
+                              var, exp.sugared().to_latex(), derivative.sugared().to_latex()),
+  }
+  println!("Elapsed time {}s", now.elapsed().as_secs());
+}
@@ -1,91 +1,448 @@
-use crate::{var, c, parser::{Expression, parse, ParseError}};
+use crate::assumptions::Assumptions;
+use crate::cost::{CostFunction, Weighted};
+use crate::parser::Expression;
+use crate::rules::Rules;
 use crate::{transformation_graph, tree_transform};
+use crate::transformation_graph::{Derivation, Graph};
+use crate::tree_transform::{Equivalence, Rewrite, Step};
 use std::rc::Rc;
-use std::collections::VecDeque;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use crate::tree_transform::simplify_via_forward_transform;
 
-const MEASURE_PER_HEIGHT: i32 = 1;
-const VARIABLE_CONST: i32 = 2;
-const CONSTANT_CONST: i32 = 1;
-
+/// The default cost of an expression, which counts every node, and variables and fractions twice.
 pub fn measure(e: &Expression) -> i32 {
-  match e {
-    Expression::Constant(_) => CONSTANT_CONST,
-    Expression::Variable(_) => VARIABLE_CONST,
-    Expression::Sum(a, b) => measure(a) + measure(b) + MEASURE_PER_HEIGHT,
-    Expression::Product(a, b) => measure(a) + measure(b) + MEASURE_PER_HEIGHT,
-    Expression::Difference(a, b) => measure(a) + measure(b) + MEASURE_PER_HEIGHT,
-    Expression::Quotient(a, b) => measure(a) + measure(b) + MEASURE_PER_HEIGHT,
-    Expression::Power(a, b) => measure(a) + measure(b) + MEASURE_PER_HEIGHT,
-  }
+  Weighted::default().cost(e)
 }
 
 // completely arbitrary
-fn max_measure(min_measure: i32) -> i32 {
-  return min_measure * 2 + 3;
+pub fn max_measure(min_measure: i32) -> i32 {
+  min_measure * 2 + 3
 }
 
-pub fn find_min_equivalent_expr(e: Expression) -> Expression {
+/// Stops a search from elsewhere, e.g. from another thread while it runs. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+  pub fn new() -> CancellationToken {
+    CancellationToken::default()
+  }
+
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
+/// Which expression a search rewrites next.
+#[derive(Clone, Default)]
+pub enum Strategy {
+  /// Everything one rewrite away from the start, then everything two away, and so on.
+  #[default]
+  BreadthFirst,
+  /// The cheapest expression waiting, by its cost plus the heuristic's guess at how much cheaper it can get,
+  /// which should be 0 or less. With a beam width, only that many of the cheapest are kept waiting.
+  BestFirst { heuristic: Option<Heuristic>, beam_width: Option<usize> },
+}
+
+/// Guesses how much cheaper an expression can get. It can hold on to what it needs, like a target to aim for.
+pub type Heuristic = Arc<dyn Fn(&Expression) -> i32 + Send + Sync>;
+
+impl fmt::Debug for Strategy {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Strategy::BreadthFirst => write!(f, "BreadthFirst"),
+      Strategy::BestFirst { heuristic, beam_width } => f.debug_struct("BestFirst")
+        .field("heuristic", &heuristic.as_ref().map(|_| "..."))
+        .field("beam_width", beam_width)
+        .finish(),
+    }
+  }
+}
+
+/// How to search, and how much a search may do before it settles for the smallest expression found so far.
+/// Breadth first with nothing limited by default, e.g. `SearchConfig { timeout: Some(..), ..SearchConfig::default() }`
+/// only limits the time.
+#[derive(Debug, Clone, Default)]
+pub struct SearchConfig {
+  pub strategy: Strategy,
+  /// How many expressions the graph may hold.
+  pub max_nodes: Option<usize>,
+  /// How many rewrites away from the start the search may go.
+  pub max_depth: Option<usize>,
+  pub timeout: Option<Duration>,
+  pub cancellation: CancellationToken,
+}
+
+/// Why a search stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+  /// Every expression the rules reach, within `max_measure` of the smallest, was rewritten.
+  Exhausted,
+  NodeLimit,
+  /// Everything within the depth limit was rewritten, but there was more beyond it.
+  DepthLimit,
+  /// Everything in the beam was rewritten, but some expressions were dropped from it.
+  BeamWidth,
+  Timeout,
+  Cancelled,
+}
+
+/// The smallest expression a search found, and what the search did to find it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimplifyResult {
+  pub expression: Expression,
+  pub measure: i32,
+  /// How many rewrites away from the start the expression was found.
+  pub depth: usize,
+  /// How many expressions the search reached.
+  pub graph_size: usize,
+  /// How many of those it rewrote in turn.
+  pub expanded: usize,
+  /// How many rewrites it found between them.
+  pub edges: usize,
+  pub elapsed: Duration,
+  pub stop_reason: StopReason,
+  /// How the expression follows from the start.
+  pub derivation: Derivation,
+}
+
+/// Hears how a search is going, e.g. to show progress. Every method does nothing by default.
+pub trait Observer {
+  fn started(&mut self, _start: &Expression) {}
+  /// Every expression fewer than depth rewrites away has been rewritten. Only breadth first searches say so.
+  fn reached_depth(&mut self, _depth: usize, _graph: &Graph) {}
+  /// The search found a smaller expression than any before.
+  fn improved(&mut self, _e: &Expression, _measure: i32, _depth: usize) {}
+  fn finished(&mut self, _graph: &Graph, _result: &SimplifyResult) {}
+}
+
+/// Doesn't hear anything, for when only the result matters.
+pub struct Silent;
+
+impl Observer for Silent {}
+
+/// Returns the smallest expression found to be equivalent to e by the rules, and how it was derived from e,
+/// given what is known about its variables. Smallest is by cost, e.g. `Weighted::default()` for `measure`.
+/// Settles for the smallest found so far once a budget in config is spent.
+pub fn find_min_equivalent_expr(e: Expression, rules: &Rules, known: &Assumptions, cost: &dyn CostFunction,
+                                config: &SearchConfig, observer: &mut dyn Observer) -> SimplifyResult {
+  let started = Instant::now();
   let root_exp = Rc::new(e);
-  let mut min_exp_measure = measure(root_exp.as_ref());
+  let mut min_exp_measure = cost.cost(root_exp.as_ref());
   let mut min_exp = Rc::clone(&root_exp);
   let mut min_exp_depth = 0;
-  println!("Parsed expression: {}", root_exp);
+  let mut expanded = 0;
+  observer.started(root_exp.as_ref());
   // The graph takes ownership and persists `e`, but i can't figure out how to tell that to the compiler,
   // so we need to clone it.
   let mut graph = transformation_graph::create_graph(Rc::clone(&root_exp));
-  let equivalences = tree_transform::get_transformations();
-  let simple_equivalences = tree_transform::get_simple_transformations();
-  let mut to_transform = VecDeque::new();
-  to_transform.push_back((Rc::clone(&root_exp), 0));
+  // Waiting to be rewritten, cheapest first. Breadth first search is best first by depth.
+  // Ties go to whichever was queued first.
+  let priority = |e: &Expression, measure: i32, depth: usize| match &config.strategy {
+    Strategy::BreadthFirst => depth as i32,
+    Strategy::BestFirst { heuristic, .. } => measure + heuristic.as_ref().map_or(0, |heuristic| heuristic(e)),
+  };
+  let mut to_transform = BTreeMap::new();
+  let mut queued = 0;
+  let key = (priority(root_exp.as_ref(), min_exp_measure, 0), queued);
+  to_transform.insert(key, (Rc::clone(&root_exp), 0, min_exp_measure));
   let mut prev_depth = 0;
-  while !to_transform.is_empty() {
-    let (e, depth) = to_transform.pop_front().unwrap();
-    if depth > prev_depth {
-      println!("Reached depth {} of transformations, with graph size {}", depth, graph.size());
-      prev_depth = depth;
+  let mut stop_reason = StopReason::Exhausted;
+  'search: while let Some((_, (e, depth, e_measure))) = to_transform.pop_first() {
+    if config.cancellation.is_cancelled() {
+      stop_reason = StopReason::Cancelled;
+      break;
+    }
+    if config.timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+      stop_reason = StopReason::Timeout;
+      break;
     }
-    for equivalence in equivalences.iter() {
-      for transformed in tree_transform::transform(e.as_ref(), equivalence).into_iter() {
-        let transformed = simplify_via_forward_transform(transformed, &simple_equivalences);
-        // measure transformed to make sure it does not stray too far from root_exp
-        let transformed_measure = measure(&transformed);
-        let transformed = Rc::new(transformed);
-        if transformed_measure >= max_measure(min_exp_measure) { continue; }
-        if transformed_measure < min_exp_measure {
-          min_exp_measure = transformed_measure;
-          min_exp = Rc::clone(&transformed);
-          min_exp_depth = depth+1;
+    if config.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+      stop_reason = StopReason::DepthLimit;
+      match config.strategy {
+        // the rest of the queue is at least as deep, and gets left as it is
+        Strategy::BreadthFirst => break,
+        Strategy::BestFirst { .. } => continue,
+      }
+    }
+    match config.strategy {
+      Strategy::BreadthFirst => if depth > prev_depth {
+        observer.reached_depth(depth, &graph);
+        prev_depth = depth;
+      },
+      // something smaller may have been found since e was queued, so e strays too far from it now
+      Strategy::BestFirst { .. } => if e_measure >= max_measure(min_exp_measure) {
+        continue;
+      },
+    }
+    expanded += 1;
+    let mut rewrites: Vec<(&Equivalence, Rewrite)> = Vec::new();
+    // Everything else in the graph has been through the simple transformations already.
+    if depth == 0 {
+      for equivalence in rules.simple.iter() {
+        for rewrite in tree_transform::transform(e.as_ref(), equivalence, known).into_iter() {
+          rewrites.push((equivalence, rewrite));
+        }
+      }
+    }
+    for equivalence in rules.equivalences.iter() {
+      for rewrite in tree_transform::transform(e.as_ref(), equivalence, known).into_iter() {
+        rewrites.push((equivalence, rewrite));
+      }
+    }
+    for (equivalence, rewrite) in rewrites.into_iter() {
+      let step = Step::new(rewrite, equivalence);
+      let (transformed, simple_steps) = simplify_via_forward_transform(step.exp.clone(), &rules.simple, known);
+      let mut steps = vec![step];
+      steps.extend(simple_steps);
+      // measure transformed to make sure it does not stray too far from root_exp
+      let transformed_measure = cost.cost(&transformed);
+      let transformed = Rc::new(transformed);
+      if transformed_measure >= max_measure(min_exp_measure) { continue; }
+      if transformed_measure < min_exp_measure {
+        min_exp_measure = transformed_measure;
+        min_exp = Rc::clone(&transformed);
+        min_exp_depth = depth+1;
+        observer.improved(min_exp.as_ref(), min_exp_measure, min_exp_depth);
+      }
+      if graph.add_node(Rc::clone(&e), Rc::clone(&transformed), equivalence, steps) {
+        queued += 1;
+        let key = (priority(transformed.as_ref(), transformed_measure, depth+1), queued);
+        to_transform.insert(key, (transformed, depth+1, transformed_measure));
+        if let Strategy::BestFirst { beam_width: Some(beam_width), .. } = &config.strategy {
+          if to_transform.len() > *beam_width {
+            to_transform.pop_last();
+            if stop_reason == StopReason::Exhausted {
+              stop_reason = StopReason::BeamWidth;
+            }
+          }
         }
-        if graph.add_node(Rc::clone(&e), Rc::clone(&transformed), equivalence) {
-          // println!("{}: {} transformed by {} becomes {}", depth+1, e, equivalence, transformed);
-          to_transform.push_back((transformed, depth+1));
+        if config.max_nodes.is_some_and(|max_nodes| graph.size() >= max_nodes) {
+          stop_reason = StopReason::NodeLimit;
+          break 'search;
         }
       }
     }
   }
-  println!("Graph:\n{}", graph);
-  println!("{} with measure {} is distance {} away from {}", min_exp, min_exp_measure, min_exp_depth, root_exp);
-  min_exp.as_ref().clone()
+  let result = SimplifyResult {
+    expression: min_exp.as_ref().clone(),
+    measure: min_exp_measure,
+    depth: min_exp_depth,
+    graph_size: graph.size(),
+    expanded,
+    edges: graph.edge_count(),
+    elapsed: started.elapsed(),
+    stop_reason,
+    derivation: graph.derivation(min_exp.as_ref()).unwrap(),
+  };
+  observer.finished(&graph, &result);
+  result
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::{var, c, parser::{parse, ParseError, Rational}};
+  use crate::condition::Condition;
 
   #[test]
   fn test_multiply_by_zero_expression() {
     // Test with expression constructors.
-    assert_eq!(find_min_equivalent_expr(var!("x") * c!(0)), c!(0));
+    let result = simplify(var!("x") * c!(0), &Assumptions::new());
+    assert_eq!(result.expression, c!(0));
+  }
+
+  // Simplifies with the default rules, quietly.
+  fn simplify(e: Expression, known: &Assumptions) -> SimplifyResult {
+    find_min_equivalent_expr(e, &Rules::default(), known, &Weighted::default(), &SearchConfig::default(), &mut Silent)
   }
 
   fn assert_min_equivalent(e: &str, min: &str) -> Result<(), ParseError> {
     let e = parse(e)?;
     let min = parse(min)?;
-    assert_eq!(find_min_equivalent_expr(e), min);
+    assert_eq!(simplify(e, &Assumptions::new()).expression, min);
+    Ok(())
+  }
+
+  // Remembers what it heard.
+  #[derive(Default)]
+  struct Recorder {
+    started: usize,
+    depths: Vec<usize>,
+    improvements: Vec<i32>,
+    finished: Option<SimplifyResult>,
+  }
+
+  impl Observer for Recorder {
+    fn started(&mut self, _start: &Expression) { self.started += 1; }
+    fn reached_depth(&mut self, depth: usize, _graph: &Graph) { self.depths.push(depth); }
+    fn improved(&mut self, _e: &Expression, measure: i32, _depth: usize) { self.improvements.push(measure); }
+    fn finished(&mut self, _graph: &Graph, result: &SimplifyResult) { self.finished = Some(result.clone()); }
+  }
+
+  #[test]
+  fn test_simplify_result() -> Result<(), ParseError> {
+    let mut recorder = Recorder::default();
+    let result = find_min_equivalent_expr(parse("x*y/x")?, &Rules::default(), &Assumptions::new(),
+                                          &Weighted::default(), &SearchConfig::default(), &mut recorder);
+    assert_eq!(result.expression, var!("y"));
+    assert_eq!(result.stop_reason, StopReason::Exhausted);
+    assert_eq!(result.measure, measure(&result.expression));
+    assert!(result.depth > 0 && result.depth <= result.derivation.steps.len());
+    assert!(result.expanded <= result.graph_size && result.graph_size <= result.edges + 1);
+    assert_eq!(recorder.started, 1);
+    assert!(recorder.depths.windows(2).all(|depths| depths[0] < depths[1]));
+    assert!(recorder.improvements.windows(2).all(|measures| measures[0] > measures[1]));
+    assert_eq!(recorder.improvements.last(), Some(&result.measure));
+    assert_eq!(recorder.finished, Some(result));
+    Ok(())
+  }
+
+  fn search(e: &str, config: &SearchConfig) -> Result<SimplifyResult, ParseError> {
+    Ok(find_min_equivalent_expr(parse(e)?, &Rules::default(), &Assumptions::new(), &Weighted::default(), config,
+                                &mut Silent))
+  }
+
+  #[test]
+  fn test_search_budgets() -> Result<(), ParseError> {
+    let e = "(a^2+2*a*b+b^2)/(a+b)";
+    let config = SearchConfig { max_nodes: Some(50), ..SearchConfig::default() };
+    let result = search(e, &config)?;
+    assert_eq!(result.stop_reason, StopReason::NodeLimit);
+    assert_eq!(result.graph_size, 50);
+    // the best found so far, which can be derived from the start
+    assert_eq!(result.derivation.start, parse(e)?);
+    assert!(result.measure <= measure(&parse(e)?));
+
+    let config = SearchConfig { max_depth: Some(1), ..SearchConfig::default() };
+    let result = search(e, &config)?;
+    assert_eq!(result.stop_reason, StopReason::DepthLimit);
+    assert!(result.depth <= 1);
+
+    // a search that has nowhere further to go isn't stopped by its depth limit
+    let config = SearchConfig { max_depth: Some(100), ..SearchConfig::default() };
+    assert_eq!(search("x*1", &config)?.stop_reason, StopReason::Exhausted);
+
+    let config = SearchConfig { timeout: Some(Duration::from_millis(0)), ..SearchConfig::default() };
+    let result = search(e, &config)?;
+    assert_eq!(result.stop_reason, StopReason::Timeout);
+    assert_eq!(result.expression, parse(e)?);
+
+    let config = SearchConfig::default();
+    config.cancellation.clone().cancel();
+    assert_eq!(search(e, &config)?.stop_reason, StopReason::Cancelled);
     Ok(())
   }
 
+  #[test]
+  fn test_best_first() -> Result<(), ParseError> {
+    let e = "(a^2+2*a*b+b^2)/(a+b)";
+    let breadth_first = search(e, &SearchConfig::default())?;
+    let strategy = Strategy::BestFirst { heuristic: None, beam_width: None };
+    let best_first = search(e, &SearchConfig { strategy, ..SearchConfig::default() })?;
+    assert_eq!(best_first.expression, parse("a+b")?);
+    assert_eq!(best_first.measure, breadth_first.measure);
+    assert_eq!(best_first.stop_reason, StopReason::Exhausted);
+    assert!(best_first.expanded * 10 < breadth_first.expanded);
+
+    let strategy = Strategy::BestFirst { heuristic: None, beam_width: Some(1) };
+    let beam = search("(x*y+x*z)/x", &SearchConfig { strategy, ..SearchConfig::default() })?;
+    assert_eq!(beam.stop_reason, StopReason::BeamWidth);
+    assert_eq!(beam.derivation.start, parse("(x*y+x*z)/x")?);
+
+    // a heuristic that guesses anything without a quotient is as small as it gets
+    let quotients = |e: &Expression| match e {
+      Expression::Quotient(_, _) => 0,
+      _ => -100,
+    };
+    let strategy = Strategy::BestFirst { heuristic: Some(Arc::new(quotients)), beam_width: Some(1) };
+    let result = search("(x*y+x*z)/x", &SearchConfig { strategy, ..SearchConfig::default() })?;
+    // z+y, as it happens
+    assert_eq!(result.measure, measure(&parse("y+z")?));
+
+    // heuristics can aim for something, like A* for a known target
+    let target = parse("a+b")?;
+    let distance = move |e: &Expression| if *e == target { -1 } else { 0 };
+    let strategy = Strategy::BestFirst { heuristic: Some(Arc::new(distance)), beam_width: None };
+    let result = search("(a^2+2*a*b+b^2)/(a+b)", &SearchConfig { strategy, ..SearchConfig::default() })?;
+    assert_eq!(result.expression, parse("a+b")?);
+    Ok(())
+  }
+
+  #[test]
+  fn test_derivation() -> Result<(), ParseError> {
+    let e = parse("(a*b)/a")?;
+    let SimplifyResult { expression: min, derivation, .. } = simplify(e.clone(), &Assumptions::new());
+    assert_eq!(derivation.start, e);
+    assert_eq!(derivation.steps.last().unwrap().exp, min);
+    assert_eq!(derivation.assumptions(), vec![Condition::NonZero(var!("a"))]);
+    // no step leaves the expression as it was, even with eval_const, which gives back any constant, as a simple rule
+    let rules = Rules::parse("a*b <-> b*a\neval_const\na*0 -> 0 [simple]\neval_const [simple]").unwrap();
+    let derivation = find_min_equivalent_expr(parse("x*0+(2+3)")?, &rules, &Assumptions::new(), &Weighted::default(),
+                                              &SearchConfig::default(), &mut Silent).derivation;
+    assert_eq!(derivation.steps.last().unwrap().exp, c!(5));
+    let mut previous = &derivation.start;
+    for step in derivation.steps.iter() {
+      assert_ne!(&step.exp, previous, "{}", derivation);
+      previous = &step.exp;
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_assumptions() -> Result<(), ParseError> {
+    let SimplifyResult { expression: min, derivation, .. } = simplify(parse("x/x")?, &Assumptions::new());
+    assert_eq!(min, c!(1));
+    assert_eq!(derivation.assumptions(), vec![Condition::NonZero(var!("x"))]);
+    // nothing to assume about constants
+    let SimplifyResult { expression: min, derivation, .. } = simplify(parse("(2+3)/5")?, &Assumptions::new());
+    assert_eq!(min, c!(1));
+    assert!(derivation.assumptions().is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_known_domains() -> Result<(), ParseError> {
+    let mut known = Assumptions::new();
+    known.assume_str("x != 0").unwrap();
+    let SimplifyResult { expression: min, derivation, .. } = simplify(parse("x/x")?, &known);
+    assert_eq!(min, c!(1));
+    assert!(derivation.assumptions().is_empty());
+    known.assume_str("y > 0").unwrap();
+    assert_eq!(simplify(parse("sqrt(y^2)")?, &known).expression, var!("y"));
+    let SimplifyResult { expression: min, derivation, .. } = simplify(parse("exp(ln(x*y))/x")?, &known);
+    assert_eq!(min, var!("y"));
+    // x*y > 0 still has to be assumed, but not x != 0
+    assert_eq!(derivation.assumptions().len(), 1);
+    assert!(!derivation.assumptions().contains(&Condition::NonZero(var!("x"))));
+    Ok(())
+  }
+
+  #[test]
+  fn test_power_of_power_requires_domain() -> Result<(), ParseError> {
+    let simple_equivalences = Rules::default().simple;
+    let e = parse("(x^a)^b")?;
+    let mut known = Assumptions::new();
+    assert_eq!(simplify_via_forward_transform(e.clone(), &simple_equivalences, &known).0, e);
+    known.assume_str("x > 0").unwrap();
+    assert_eq!(simplify_via_forward_transform(e, &simple_equivalences, &known).0, parse("x^(a*b)")?);
+    Ok(())
+  }
+
+  #[test]
+  fn test_failed_condition() -> Result<(), ParseError> {
+    assert_min_equivalent("0^0", "0^0")?;
+    assert_min_equivalent("(1-1)^0", "0^0")
+  }
+
   #[test]
   fn test_multiply_by_zero() -> Result<(), ParseError> {
     // Test with parsing.
@@ -97,6 +454,60 @@ mod tests {
     assert_min_equivalent("1+2^2*9/6-3", "4")
   }
 
+  fn fraction(n: i32, d: i32) -> Expression {
+    Expression::Constant(Rational::new(n.into(), d.into()))
+  }
+
+  #[test]
+  fn test_rational_constants() -> Result<(), ParseError> {
+    assert_eq!(simplify(parse("1/2+1/3")?, &Assumptions::new()).expression, fraction(5, 6));
+    assert_eq!(simplify(parse("2^(-2)")?, &Assumptions::new()).expression, fraction(1, 4));
+    assert_eq!(simplify(parse("(8/27)^(-2/3)")?, &Assumptions::new()).expression, fraction(9, 4));
+    Ok(())
+  }
+
+  #[test]
+  fn test_big_constants() -> Result<(), ParseError> {
+    assert_min_equivalent("2^40", "1099511627776")?;
+    assert_min_equivalent("99999999999999999999*99999999999999999999", "9999999999999999999800000000000000000001")?;
+    assert_min_equivalent("(-1)^100000000001", "-1")
+  }
+
+  #[test]
+  fn test_huge_power_stays_symbolic() -> Result<(), ParseError> {
+    assert_min_equivalent("2^1000000000", "2^1000000000")
+  }
+
+  #[test]
+  fn test_huge_repeated_product() -> Result<(), ParseError> {
+    assert_min_equivalent("1000000000*a+a", "1000000001*a")
+  }
+
+  #[test]
+  fn test_irrational_power_stays_symbolic() -> Result<(), ParseError> {
+    assert_eq!(simplify(parse("2^(1/2)")?, &Assumptions::new()).expression, c!(2) ^ fraction(1, 2));
+    Ok(())
+  }
+
+  #[test]
+  fn test_pythagorean_identity() -> Result<(), ParseError> {
+    assert_min_equivalent("sin(x)^2+cos(x)^2", "1")?;
+    assert_min_equivalent("cos(x)^2+sin(x)^2", "1")
+  }
+
+  #[test]
+  fn test_logarithms() -> Result<(), ParseError> {
+    assert_min_equivalent("ln(exp(x))", "x")?;
+    assert_min_equivalent("exp(ln(x*y))", "x*y")?;
+    assert_min_equivalent("ln(x)+ln(y)", "ln(x*y)")
+  }
+
+  #[test]
+  fn test_functions_of_constants() -> Result<(), ParseError> {
+    assert_min_equivalent("sqrt(9)*a+exp(0)", "3*a+1")?;
+    assert_min_equivalent("abs(-3)*ln(1)", "0")
+  }
+
   #[test]
   fn test_multiply_by_two() -> Result<(), ParseError> {
     assert_min_equivalent("a*b+a*b", "2*(a*b)")
@@ -174,8 +585,7 @@ mod tests {
   }
 
   #[test]
-  #[ignored]
-  fn test_longer_factoring_and_cancellation() -> Result<(), ParseError> {
+  fn test_difference_of_squares_cancellation() -> Result<(), ParseError> {
     assert_min_equivalent("(a^2-b^2)/(a-b)", "a+b")
   }
 }
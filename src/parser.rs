@@ -1,29 +1,89 @@
+use std::collections::HashMap;
 use std::fmt;
-use regex::Regex;
+use crate::lexer::{tokenize, Lexeme, Token};
+use std::ops::Range;
 use std::ops::{Mul, Add, Sub, Div, BitXor};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_integer::Integer;
+use num_traits::{One, Signed, ToPrimitive, Zero, pow::Pow};
+
+/// Exact constants: a numerator and denominator of arbitrary size.
+pub type Rational = BigRational;
 
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Expression {
-  Constant(i32),
+  Constant(Rational),
   Variable(String),
   Sum(Box<Expression>, Box<Expression>),
   Product(Box<Expression>, Box<Expression>),
   Difference(Box<Expression>, Box<Expression>),
   Quotient(Box<Expression>, Box<Expression>),
   Power(Box<Expression>, Box<Expression>),
+  Apply(Function, Box<Expression>),
+}
+
+/// Elementary functions of one argument, written like `sin(x)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Function {
+  Sin,
+  Cos,
+  Tan,
+  Exp,
+  Ln,
+  Sqrt,
+  Abs,
+}
+
+impl Function {
+  pub const ALL: [Function; 7] =
+    [Function::Sin, Function::Cos, Function::Tan, Function::Exp, Function::Ln, Function::Sqrt, Function::Abs];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Function::Sin => "sin",
+      Function::Cos => "cos",
+      Function::Tan => "tan",
+      Function::Exp => "exp",
+      Function::Ln => "ln",
+      Function::Sqrt => "sqrt",
+      Function::Abs => "abs",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Function> {
+    Function::ALL.iter().cloned().find(|f| f.name() == name)
+  }
+
+  pub fn apply(self, e: Expression) -> Expression {
+    Expression::Apply(self, e.into())
+  }
+
+  // f(x), if it is rational.
+  pub(crate) fn eval_const(self, x: &Rational) -> Option<Rational> {
+    match self {
+      Function::Sin | Function::Tan if x.is_zero() => Some(Rational::zero()),
+      Function::Cos | Function::Exp if x.is_zero() => Some(Rational::one()),
+      Function::Ln if x.is_one() => Some(Rational::zero()),
+      Function::Sqrt => rational_pow(x, &Rational::new(1.into(), 2.into())),
+      Function::Abs => Some(x.abs()),
+      _ => None,
+    }
+  }
 }
 
 // usage: var!("a")
 #[macro_export]
 macro_rules! var {
-  ($s:expr) => { Expression::Variable(($s).into()) }
+  ($s:expr) => { $crate::Expression::Variable(($s).into()) }
 }
 
 // usage: c!(1)
+// for integers. build fractions with Expression::Constant(Rational::new(n, d))
 #[macro_export]
 macro_rules! c {
-  ($c:expr) => { Expression::Constant($c) }
+  ($c:expr) => { $crate::Expression::Constant($crate::Rational::from_integer(($c).into())) }
 }
 
 impl Default for Expression {
@@ -32,61 +92,147 @@ impl Default for Expression {
   }
 }
 
+// Prints only the parentheses that parsing it back needs, so that parse(e.to_string()) == e.
 impl fmt::Display for Expression {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    match self {
-      Expression::Constant(c) => write!(f, "{}", c),
-      Expression::Variable(v) => write!(f, "{}", v),
-      Expression::Sum(e1, e2) => write!(f, "({})+({})", e1, e2),
-      Expression::Product(e1, e2) => write!(f, "({})*({})", e1, e2),
-      Expression::Difference(e1, e2) => write!(f, "({})-({})", e1, e2),
-      Expression::Quotient(e1, e2) => write!(f, "({})/({})", e1, e2),
-      Expression::Power(e1, e2) => write!(f, "({})^({})", e1, e2),
+    self.write(f, true)
+  }
+}
+
+// How tightly each kind of expression binds, for deciding where parentheses are needed.
+fn precedence(e: &Expression) -> u8 {
+  match e {
+    Expression::Sum(_, _) | Expression::Difference(_, _) => 1,
+    Expression::Product(_, _) | Expression::Quotient(_, _) => 2,
+    Expression::Power(_, _) => 3,
+    Expression::Constant(_) | Expression::Variable(_) | Expression::Apply(_, _) => 4,
+  }
+}
+
+impl Expression {
+  // leftmost: whether this is at the start of the output, or of parentheses,
+  // where a - can only be the sign of a number.
+  fn write(&self, f: &mut fmt::Formatter, leftmost: bool) -> fmt::Result {
+    let (a, operator, b) = match self {
+      // fractions are written as a literal, so they aren't read back as a quotient
+      Expression::Constant(c) if !c.is_integer() => return write!(f, "[{}]", c),
+      Expression::Constant(c) => return write!(f, "{}", c),
+      Expression::Variable(v) => return write!(f, "{}", v),
+      // the parentheses of the argument are the same as those around an operand
+      Expression::Apply(func, a) => {
+        write!(f, "{}", func.name())?;
+        return a.parenthesized(f);
+      },
+      Expression::Sum(a, b) => (a, "+", b),
+      Expression::Product(a, b) => (a, "*", b),
+      Expression::Difference(a, b) => (a, "-", b),
+      Expression::Quotient(a, b) => (a, "/", b),
+      Expression::Power(a, b) => (a, "^", b),
+    };
+    a.write_operand(f, self, false, leftmost)?;
+    write!(f, "{}", operator)?;
+    b.write_operand(f, self, true, false)
+  }
+
+  fn write_operand(&self, f: &mut fmt::Formatter, parent: &Expression, right: bool, leftmost: bool) -> fmt::Result {
+    let parentheses = match self {
+      // -3^2 would be unclear, and a*-3 looks like a typo
+      Expression::Constant(c) if c.is_integer() && c.is_negative() =>
+        !leftmost || matches!(parent, Expression::Power(_, _)),
+      // ^ is right associative, and every other operator left associative
+      _ => precedence(self) < precedence(parent) ||
+        (right != matches!(parent, Expression::Power(_, _)) && precedence(self) == precedence(parent)),
+    };
+    if parentheses {
+      self.parenthesized(f)
+    } else {
+      self.write(f, leftmost)
     }
   }
+
+  fn parenthesized(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "(")?;
+    self.write(f, true)?;
+    write!(f, ")")
+  }
 }
 
 impl Expression {
-  pub fn unwrap_constant(&self) -> Option<i32> {
+  pub fn unwrap_constant(&self) -> Option<Rational> {
     match self {
-      Expression::Constant(c) => Some(*c),
+      Expression::Constant(c) => Some(c.clone()),
       _ => None
     }
   }
 
-  fn do_constant_math<F: Fn(i32, i32) -> Option<i32>>(a: &Expression, b: &Expression, math: F) -> Option<Expression> {
-    Some(Expression::Constant(math(
-      a.eval_const()?.unwrap_constant()?,
-      b.eval_const()?.unwrap_constant()?,
-    )?))
+  pub fn eval_const(&self) -> Option<Expression> {
+    self.constant_value().map(Expression::Constant)
   }
 
-  pub fn eval_const(&self) -> Option<Expression> {
+  // Only finds the operands, and leaves the arithmetic to `operate`, so each level of a deep expression
+  // takes little of the stack.
+  fn constant_value(&self) -> Option<Rational> {
     match self {
-      Expression::Constant(_) => Some(self.clone()),
+      Expression::Constant(c) => Some(c.clone()),
       Expression::Variable(_) => None,
-      Expression::Sum(a, b) =>
-        Self::do_constant_math(a, b, |x, y| Some(x+y)),
-      Expression::Product(a, b) =>
-        Self::do_constant_math(a, b, |x, y| Some(x*y)),
-      Expression::Difference(a, b) =>
-        Self::do_constant_math(a, b, |x, y| Some(x-y)),
-      Expression::Quotient(a, b) =>
-        Self::do_constant_math(a, b, |x, y| if y != 0 && x % y == 0 {
-          Some(x / y)
-        } else {
-          None
-        }),
-      Expression::Power(a, b) =>
-        Self::do_constant_math(a, b, |x, y| if (x == 0 && y == 0) || y < 0 {
-          None // TODO: deal with exact roots
-        } else {
-          Some(x.pow(y as u32))
-        }),
+      Expression::Apply(func, a) => func.eval_const(&a.constant_value()?),
+      Expression::Sum(a, b) | Expression::Difference(a, b) | Expression::Product(a, b) |
+      Expression::Quotient(a, b) | Expression::Power(a, b) => {
+        let x = a.constant_value()?;
+        self.operate(x, b.constant_value()?)
+      },
+    }
+  }
+
+  // The operator of self applied to x and y.
+  fn operate(&self, x: Rational, y: Rational) -> Option<Rational> {
+    match self {
+      Expression::Sum(_, _) => Some(x + y),
+      Expression::Difference(_, _) => Some(x - y),
+      Expression::Product(_, _) => Some(x * y),
+      Expression::Quotient(_, _) if y.is_zero() => None,
+      Expression::Quotient(_, _) => Some(x / y),
+      Expression::Power(_, _) => rational_pow(&x, &y),
+      _ => None,
     }
   }
 }
 
+// Powers whose result would take more bits than this are left unevaluated,
+// so that e.g. 2^(10^9) doesn't eat all the memory.
+const MAX_POWER_BITS: u64 = 1 << 16;
+
+// x^y, if it is rational. Fractional exponents only work out for exact roots, e.g. 4^(1/2) or 8^(-2/3).
+pub(crate) fn rational_pow(x: &Rational, y: &Rational) -> Option<Rational> {
+  if x.is_zero() && !y.is_positive() {
+    return None
+  }
+  let root = y.denom().to_u32()?;
+  let base = if root == 1 {
+    x.clone()
+  } else {
+    // even roots of negative numbers aren't real
+    if x.is_negative() && root % 2 == 0 { return None }
+    let numer = x.numer().nth_root(root);
+    let denom = x.denom().nth_root(root);
+    if Pow::pow(&numer, root) != *x.numer() || Pow::pow(&denom, root) != *x.denom() { return None }
+    Rational::new(numer, denom)
+  };
+  let exponent = y.numer();
+  // 0, 1 and -1 stay small no matter how big the exponent is
+  if base.is_zero() || base.is_one() {
+    return Some(base)
+  }
+  if base == -Rational::one() {
+    return Some(if exponent.is_even() { Rational::one() } else { base })
+  }
+  let bits = base.numer().bits().max(base.denom().bits());
+  if bits.saturating_mul(exponent.magnitude().to_u64()?) > MAX_POWER_BITS {
+    return None
+  }
+  Some(Pow::pow(base, exponent.to_i32()?))
+}
+
 impl Mul<Expression> for Expression {
   type Output = Expression;
 
@@ -130,120 +276,597 @@ impl BitXor<Expression> for Expression {
 
 #[derive(Debug)]
 pub struct ParseError {
+  /// The bytes of the input the error is about, if known. Empty at the end of the input.
+  span: Option<Range<usize>>,
+  /// What would have been accepted instead, e.g. `')'` or `a number`.
+  expected: Vec<String>,
   msg: String,
 }
 
+impl ParseError {
+  pub(crate) fn new(span: Range<usize>, msg: String, expected: &[&str]) -> ParseError {
+    ParseError{span: Some(span), expected: expected.iter().map(|e| e.to_string()).collect(), msg}
+  }
+
+  /// The bytes of the input the error is about, if known. Empty at the end of the input.
+  pub fn span(&self) -> Option<Range<usize>> {
+    self.span.clone()
+  }
+
+  /// What would have been accepted instead, e.g. `')'` or `a number`. Empty if there's no one thing.
+  pub fn expected(&self) -> &[String] {
+    &self.expected
+  }
+
+  /// The same error in a larger input, where the part that was parsed starts at offset.
+  pub fn shifted(mut self, offset: usize) -> ParseError {
+    self.span = self.span.map(|span| span.start + offset..span.end + offset);
+    self
+  }
+
+  /// The error followed by the line of input it is about, with carets under the span, e.g.
+  /// ```text
+  /// Parse Error at position 4: unexpected ')', expected a number, a variable, a function or '('
+  ///   a+b*)
+  ///       ^
+  /// ```
+  pub fn diagnostic(&self, input: &str) -> String {
+    let span = match &self.span {
+      Some(span) if span.start <= input.len() => span,
+      _ => return self.to_string(),
+    };
+    let line_start = input[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = input[span.start..].find('\n').map_or(input.len(), |i| span.start + i);
+    let column = input[line_start..span.start].chars().count();
+    let width = input.get(span.start..span.end.min(line_end)).map_or(0, |s| s.chars().count()).max(1);
+    format!("{}\n  {}\n  {}{}", self, &input[line_start..line_end], " ".repeat(column), "^".repeat(width))
+  }
+}
+
 impl fmt::Display for ParseError {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "Parse Error: {}", self.msg)
+    match &self.span {
+      Some(span) => write!(f, "Parse Error at position {}: {}", span.start, self.msg)?,
+      None => write!(f, "Parse Error: {}", self.msg)?,
+    }
+    match self.expected.split_last() {
+      Some((last, [])) => write!(f, ", expected {}", last),
+      Some((last, others)) => write!(f, ", expected {} or {}", others.join(", "), last),
+      None => Ok(()),
+    }
+  }
+}
+
+/// What a variable name can be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Identifiers {
+  /// Letters, digits and underscores, like `x`, `rate` or `x_1`.
+  Words,
+  /// A single letter, maybe with a subscript like `x_1`, so `ab` is the variables a and b.
+  /// Function names followed by `(` are still read whole, so `xsin(y)` is x and sin(y).
+  SingleLetters,
+}
+
+/// How `parse_with` reads an expression. The default is what `parse` reads, and what Display writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+  /// Whether operands side by side are multiplied, like `2x`, `3(a+b)` and `(a+b)(a-b)`.
+  /// They bind like `*`, so `2x^2` is `2*x^2`, and `a/2x` is `a/2*x`.
+  pub implicit_multiplication: bool,
+  /// With SingleLetters, `ab` is only `a*b` with implicit multiplication too, and otherwise an error.
+  pub identifiers: Identifiers,
+}
+
+impl Default for ParseOptions {
+  fn default() -> Self {
+    ParseOptions{implicit_multiplication: false, identifiers: Identifiers::Words}
   }
 }
 
+/// Parses an expression like `x^2 + 2*sin(x)`. Whitespace and `#` comments are ignored.
+/// Expressions too deep to be worked with are an error: more than 1000 operations deep, like a sum of more than
+/// 1000 terms, or with parentheses, functions and operators nested more than 128 deep.
 pub fn parse(expr: &str) -> Result<Expression, ParseError> {
-	let (expr, leftover) = parse_sum(expr)?;
-  if leftover.is_empty() {
-    Ok(expr)
-  } else {
-    Err(ParseError{msg: format!("expected end of expression. remaining to parse: '{}'", leftover)})
+  parse_with(expr, &ParseOptions::default())
+}
+
+/// Like `parse`, but for input written by hand, e.g. `2xy + 3(a+b)` with implicit multiplication.
+pub fn parse_with(expr: &str, options: &ParseOptions) -> Result<Expression, ParseError> {
+  let lexemes = match options.identifiers {
+    Identifiers::Words => tokenize(expr),
+    Identifiers::SingleLetters => split_identifiers(tokenize(expr)),
+  };
+  let mut parser = Parser{input: expr, lexemes, next: 0, nesting: 0,
+                          implicit_multiplication: options.implicit_multiplication};
+  let (e, _) = parser.expression(0)?;
+  match parser.peek() {
+    None => Ok(e),
+    Some(Token::RightParen) => Err(ParseError::new(parser.next_span(), String::from("unmatched ')'"), &[])),
+    Some(_) => Err(parser.unexpected(&["an operator", "the end of the expression"])),
+  }
+}
+
+/// Parses substitutions like `x = a+b, y^2 = 2`, into what `Expression::substitute` takes,
+/// with parse reading each side, e.g. `parse` or `latex::parse_latex`. Error spans are positions in bindings.
+pub fn parse_bindings(bindings: &str, parse: impl Fn(&str) -> Result<Expression, ParseError>)
+                      -> Result<HashMap<Expression, Expression>, ParseError> {
+  let mut parsed = HashMap::new();
+  let mut offset = 0;
+  for binding in bindings.split(',') {
+    let (from, to) = binding.split_once('=')
+      .ok_or_else(|| ParseError::new(offset..offset + binding.len(),
+                                     format!("expected a binding like 'x = a+b', got '{}'", binding.trim()), &[]))?;
+    let from_offset = offset + from.len() - from.trim_start().len();
+    let to_offset = offset + from.len() + 1 + to.len() - to.trim_start().len();
+    let (from, to) = (from.trim(), to.trim());
+    if from.is_empty() || to.is_empty() {
+      return Err(ParseError::new(offset..offset + binding.len(), format!("missing side of binding '{}'", binding.trim()), &[]));
+    }
+    parsed.insert(parse(from).map_err(|err| err.shifted(from_offset))?, parse(to).map_err(|err| err.shifted(to_offset))?);
+    offset += binding.len() + 1;
+  }
+  Ok(parsed)
+}
+
+// What can start an operand, for errors.
+const LEAF: [&str; 5] = ["a number", "a variable", "a function", "'('", "'-'"];
+
+// How tightly an infix operator binds on its left and right. ^ is right-associative, so a^b^c is a^(b^c),
+// and the others are left-associative.
+fn binding_power(token: Token) -> Option<(u8, u8)> {
+  match token {
+    Token::Plus | Token::Minus => Some((1, 2)),
+    Token::Star | Token::Slash => Some((3, 4)),
+    Token::Caret => Some((7, 6)),
+    _ => None,
   }
 }
 
-// TODO: shift-reduce parser
+// How tightly a leading - binds: looser than ^, so -x^2 is -(x^2), and tighter than *, so -2*x is (-2)*x.
+const NEGATION_POWER: u8 = 5;
 
-type ParseResult<'a> = Result<(Expression, &'a str), ParseError>;
+// -e, as the rules like it: a negative constant, or (-1)*e.
+fn negate(e: Expression) -> Expression {
+  match e {
+    Expression::Constant(c) => Expression::Constant(-c),
+    e => c!(-1) * e,
+  }
+}
 
-// can be used at lowest (leaf) level of parse tree
-// i.e. it's a number, a variable, or a subexpression in parentheses
-fn parse_leaf(expr: &str) -> ParseResult {
-	if expr.starts_with("(") {
-    // Surprise! The leaf is a subexpression in parentheses. So we have to keep parsing.
-    let (s1, leftover) = parse_sum(expr.get(1..).unwrap())?;
-    if leftover.starts_with(")") {
-      Ok((s1, leftover.get(1..).unwrap()))
-    } else {
-      Err(ParseError{msg: String::from("missing end parenthesis")})
+// Splits identifiers into single letters with their subscripts and numbers, except for function calls.
+fn split_identifiers(lexemes: Vec<Lexeme>) -> Vec<Lexeme> {
+  let mut split = Vec::with_capacity(lexemes.len());
+  for (i, lexeme) in lexemes.iter().enumerate() {
+    let name = match lexeme.token {
+      Token::Identifier(name) => name,
+      _ => {
+        split.push(lexeme.clone());
+        continue;
+      },
+    };
+    // a function name at the end, like the sin of xsin(y)
+    let called = lexemes.get(i + 1).map(|next| next.token) == Some(Token::LeftParen);
+    let function = Function::ALL.iter().map(|func| func.name()).find(|func| called && name.ends_with(func));
+    let letters = &name[..name.len() - function.map_or(0, str::len)];
+    let mut start = 0;
+    while start < letters.len() {
+      let rest = &letters[start..];
+      let first = rest.chars().next().unwrap();
+      let length = if first.is_ascii_digit() {
+        rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len())
+      } else if rest[first.len_utf8()..].starts_with('_') {
+        // the subscript is the rest of the identifier
+        rest.len()
+      } else {
+        first.len_utf8()
+      };
+      let span = lexeme.span.start + start..lexeme.span.start + start + length;
+      let token = if first.is_ascii_digit() { Token::Number(&rest[..length]) } else { Token::Identifier(&rest[..length]) };
+      split.push(Lexeme{token, span});
+      start += length;
     }
-  } else {
-    let number_regex = Regex::new(r"^[-0-9]").unwrap();
-    if number_regex.is_match(expr) {
-      parse_literal(expr)
-    } else {
-      parse_variable(expr)
+    if let Some(function) = function {
+      split.push(Lexeme{token: Token::Identifier(function), span: lexeme.span.end - function.len()..lexeme.span.end});
     }
   }
+  split
 }
 
-fn parse_variable(mut expr: &str) -> ParseResult {
-  let mut curr_str = String::new();
-  while !expr.is_empty() {
-    if Regex::new(r"^[\w]").unwrap().is_match(expr) {
-      curr_str.push(expr.chars().next().unwrap());
-      expr = expr.get(1..).unwrap();
-    } else {
-      break;
-    }
+// What multiplying operands side by side binds like: *.
+const IMPLICIT_MULTIPLICATION_POWER: (u8, u8) = (3, 4);
+
+// How deep an expression tree may get. Anything deeper would overflow the stack wherever the expression is used
+// after, since rewriting, printing and evaluating all recurse into it. The most any of them handles
+// on a 2 MiB thread stack, unoptimized, is about 1500.
+pub(crate) const MAX_DEPTH: usize = 1000;
+
+// How many parentheses, functions and operators the parser may be inside of. Each takes far more of the stack
+// than a level of the tree does, but only input nested by hand gets anywhere near this.
+pub(crate) const MAX_NESTING: usize = 128;
+
+// The depth of a node over a subtree this deep, with the part of the input the node was parsed from.
+pub(crate) fn deeper(depth: usize, span: Range<usize>) -> Result<usize, ParseError> {
+  if depth >= MAX_DEPTH {
+    return Err(ParseError::new(span, format!("expression is more than {} operations deep", MAX_DEPTH), &[]));
   }
-  Ok((Expression::Variable(curr_str), expr))
+  Ok(depth + 1)
 }
 
-fn parse_literal(mut expr: &str) -> ParseResult {
-  let multiplier = if expr.starts_with("-") {
-    expr = expr.get(1..).unwrap();
-    -1
-  } else { 1 };
+// The error for going past MAX_NESTING at span.
+pub(crate) fn nested_too_deep(span: Range<usize>) -> ParseError {
+  let msg = format!("parentheses, functions and operators are nested more than {} deep", MAX_NESTING);
+  ParseError::new(span, msg, &[])
+}
+
+// An expression, and how deep its tree is.
+pub(crate) type Parsed = (Expression, usize);
+
+// A Pratt parser over the tokens of the input.
+struct Parser<'a> {
+  input: &'a str,
+  lexemes: Vec<Lexeme<'a>>,
+  // index of the next lexeme
+  next: usize,
+  // how many calls to `expression` the parser is inside of
+  nesting: usize,
+  implicit_multiplication: bool,
+}
+
+impl<'a> Parser<'a> {
+  fn peek(&self) -> Option<Token<'a>> {
+    self.peek_at(0)
+  }
+
+  fn peek_at(&self, offset: usize) -> Option<Token<'a>> {
+    self.lexemes.get(self.next + offset).map(|lexeme| lexeme.token)
+  }
 
-  let mut num = 0;
-  while !expr.is_empty() {
-    let first_char = expr.chars().next().unwrap();
-    if first_char < '0' || first_char > '9' {
-      break;
+  // The next token, or nothing at the end of the input.
+  fn next_span(&self) -> Range<usize> {
+    self.lexemes.get(self.next).map_or(self.input.len()..self.input.len(), |lexeme| lexeme.span.clone())
+  }
+
+  fn unexpected(&self, expected: &[&str]) -> ParseError {
+    let msg = match self.lexemes.get(self.next) {
+      Some(lexeme) => format!("unexpected '{}'", &self.input[lexeme.span.clone()]),
+      None => String::from("unexpected end of expression"),
+    };
+    ParseError::new(self.next_span(), msg, expected)
+  }
+
+  // Parses operators binding at least as tightly as min_power.
+  fn expression(&mut self, min_power: u8) -> Result<Parsed, ParseError> {
+    if self.nesting >= MAX_NESTING {
+      return Err(nested_too_deep(self.next_span()));
     }
-		num *= 10;
-    num += first_char as i32 - '0' as i32;
-    expr = expr.get(1..).unwrap();
+    self.nesting += 1;
+    let parsed = self.operators(min_power);
+    self.nesting -= 1;
+    parsed
   }
-  Ok((Expression::Constant(multiplier * num), expr))
-}
 
-fn parse_sum(expr: &str) -> ParseResult {
-  let (mut s1, mut leftover) = parse_product(expr)?;
-  while leftover.starts_with("+") || leftover.starts_with("-") {
-		let (s2, leftover2) = parse_product(leftover.get(1..).unwrap())?;
-    if leftover.starts_with("+") {
-      s1 = Expression::Sum(s1.into(), s2.into());
-    } else {
-      s1 = Expression::Difference(s1.into(), s2.into());
+  fn operators(&mut self, min_power: u8) -> Result<Parsed, ParseError> {
+    let (mut e, mut depth) = self.operand()?;
+    while let Some(op) = self.peek() {
+      let starts_operand = matches!(op, Token::Number(_) | Token::Identifier(_) | Token::LeftParen);
+      let (left_power, right_power) = match binding_power(op) {
+        Some(powers) => powers,
+        None if self.implicit_multiplication && starts_operand => IMPLICIT_MULTIPLICATION_POWER,
+        None => break,
+      };
+      if left_power < min_power {
+        break;
+      }
+      let span = self.next_span();
+      if !starts_operand {
+        self.next += 1;
+      }
+      let (rhs, rhs_depth) = self.expression(right_power)?;
+      depth = deeper(depth.max(rhs_depth), span)?;
+      e = match op {
+        Token::Plus => e + rhs,
+        Token::Minus => e - rhs,
+        Token::Slash => e / rhs,
+        Token::Caret => e ^ rhs,
+        _ => e * rhs,
+      };
     }
-    leftover = leftover2;
+    Ok((e, depth))
   }
-  Ok((s1, leftover))
-}
 
-fn parse_product(expr: &str) -> ParseResult {
-  let (mut s1, mut leftover) = parse_power(expr)?;
-  while leftover.starts_with("*") || leftover.starts_with("/") {
-    let (s2, leftover2) = parse_power(leftover.get(1..).unwrap())?;
-    if leftover.starts_with("*") {
-      s1 = Expression::Product(s1.into(), s2.into());
-    } else {
-      s1 = Expression::Quotient(s1.into(), s2.into());
+  // A number, a variable, a function call, or a subexpression in parentheses.
+  fn operand(&mut self) -> Result<Parsed, ParseError> {
+    let start = self.next_span();
+    match self.peek() {
+      Some(Token::Number(digits)) => {
+        self.next += 1;
+        Ok((Expression::Constant(Rational::from_integer(digits.parse().unwrap())), 1))
+      },
+      Some(Token::Minus) => {
+        self.next += 1;
+        let (e, depth) = self.expression(NEGATION_POWER)?;
+        Ok((negate(e), deeper(depth, start)?))
+      },
+      Some(Token::LeftBracket) => Ok((self.fraction()?, 1)),
+      Some(Token::LeftParen) => {
+        self.next += 1;
+        // Surprise! The operand is a subexpression in parentheses. So we have to keep parsing.
+        let parsed = self.expression(0)?;
+        match self.peek() {
+          Some(Token::RightParen) => {
+            self.next += 1;
+            Ok(parsed)
+          },
+          None => Err(ParseError::new(start, String::from("'(' is never closed"), &["')'"])),
+          Some(_) => Err(self.unexpected(&["an operator", "')'"])),
+        }
+      },
+      Some(Token::Identifier(name)) => {
+        self.next += 1;
+        if self.peek() != Some(Token::LeftParen) {
+          return Ok((Expression::Variable(name.to_string()), 1));
+        }
+        match Function::from_name(name) {
+          // the argument is parsed like any other parenthesized subexpression
+          Some(func) => {
+            let (argument, depth) = self.operand()?;
+            Ok((func.apply(argument), deeper(depth, start)?))
+          },
+          // x(a+b) is x*(a+b)
+          None if self.implicit_multiplication => Ok((Expression::Variable(name.to_string()), 1)),
+          None => {
+            let functions: Vec<&str> = Function::ALL.iter().map(|func| func.name()).collect();
+            Err(ParseError::new(start, format!("unknown function '{}'", name), &functions))
+          },
+        }
+      },
+      _ => Err(self.unexpected(&LEAF)),
     }
-    leftover = leftover2;
   }
-  Ok((s1, leftover))
-}
 
-fn parse_power(expr: &str) -> ParseResult {
-  let (mut s1, mut leftover) = parse_leaf(expr)?;
-  while leftover.starts_with("^") {
-    let (s2, leftover2) = parse_leaf(leftover.get(1..).unwrap())?;
-    s1 = Expression::Power(s1.into(), s2.into());
-    leftover = leftover2;
+  // A fraction constant, written like [1/2] or [-1/2], which is how they're printed.
+  // A quotient like 1/2 is only worked out to a constant by simplifying.
+  fn fraction(&mut self) -> Result<Expression, ParseError> {
+    let start = self.next_span();
+    self.next += 1;
+    let negative = self.peek() == Some(Token::Minus);
+    if negative {
+      self.next += 1;
+    }
+    let (numer, denom) = match (self.peek(), self.peek_at(1), self.peek_at(2), self.peek_at(3)) {
+      (Some(Token::Number(numer)), Some(Token::Slash), Some(Token::Number(denom)), Some(Token::RightBracket)) =>
+        (numer.parse::<BigInt>().unwrap(), denom.parse::<BigInt>().unwrap()),
+      _ => {
+        let end = self.next_span().end;
+        return Err(ParseError::new(start.start..end, String::from("brackets only go around fractions"),
+                                   &["a fraction like [1/2]"]));
+      },
+    };
+    let span = start.start..self.lexemes[self.next + 3].span.end;
+    if denom.is_zero() {
+      return Err(ParseError::new(span, String::from("the denominator of a fraction can't be 0"), &[]));
+    }
+    self.next += 4;
+    Ok(Expression::Constant(Rational::new(if negative { -numer } else { numer }, denom)))
   }
-  Ok((s1, leftover))
 }
 
-// Use for expression literals when performance is not an issue.
-pub fn expression(e: &str) -> Expression {
-  parse(e).unwrap()
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_displays(e: &str, displayed: &str) -> Result<(), ParseError> {
+    assert_eq!(parse(e)?.to_string(), displayed);
+    Ok(())
+  }
+
+  #[test]
+  fn test_minimal_parentheses() -> Result<(), ParseError> {
+    assert_displays("a+(b*c)", "a+b*c")?;
+    assert_displays("(a+b)*c", "(a+b)*c")?;
+    assert_displays("(a-b)-(c-d)", "a-b-(c-d)")?;
+    assert_displays("a/(b/c)", "a/(b/c)")?;
+    assert_displays("(a^b)^c", "(a^b)^c")?;
+    assert_displays("a^(b^c)", "a^b^c")?;
+    assert_displays("sin((a+b))*(-3)", "sin(a+b)*(-3)")?;
+    assert_displays("-3*a+(-3)^2", "-3*a+(-3)^2")
+  }
+
+  #[test]
+  fn test_fractions() -> Result<(), ParseError> {
+    let half = Expression::Constant(Rational::new(1.into(), 2.into()));
+    assert_eq!(parse("[1/2]")?, half);
+    assert_eq!(parse("[-6/4]*x")?, Expression::Constant(Rational::new((-3).into(), 2.into())) * var!("x"));
+    assert_eq!(half.to_string(), "[1/2]");
+    assert_eq!((var!("x") ^ half).to_string(), "x^[1/2]");
+    // what was typed is kept, and only simplifying works it out
+    assert_eq!(parse("(1/2)")?, c!(1) / c!(2));
+    assert_displays("x^(1/2)", "x^(1/2)")?;
+    assert_displays("1/2", "1/2")?;
+    assert_eq!(parse("2*[x]").unwrap_err().to_string(),
+               "Parse Error at position 2: brackets only go around fractions, expected a fraction like [1/2]");
+    assert_eq!(parse("[1/0]").unwrap_err().to_string(),
+               "Parse Error at position 0: the denominator of a fraction can't be 0");
+    Ok(())
+  }
+
+  // xorshift, for random expressions
+  fn next(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+  }
+
+  fn random_expression(state: &mut u64, depth: u32) -> Expression {
+    let choice = if depth == 0 { next(state) % 4 } else { next(state) % 11 };
+    let n = (next(state) % 7) as i64 - 3;
+    let mut operand = || Box::new(random_expression(state, depth - 1));
+    match choice {
+      0 => c!(n),
+      1 => Expression::Constant(Rational::new(n.into(), 2.into())),
+      2 => var!("x"),
+      3 => var!("y"),
+      4 => Expression::Sum(operand(), operand()),
+      5 => Expression::Product(operand(), operand()),
+      6 => Expression::Difference(operand(), operand()),
+      7 => Expression::Quotient(operand(), operand()),
+      8 | 9 => Expression::Power(operand(), operand()),
+      _ => Function::Sin.apply(*operand()),
+    }
+  }
+
+  #[test]
+  fn test_display_round_trips() -> Result<(), ParseError> {
+    let mut state = 0x2545_F491_4F6C_DD1D;
+    for _ in 0..2000 {
+      let e = random_expression(&mut state, 4);
+      assert_eq!(parse(&e.to_string())?, e, "{}", e);
+    }
+    Ok(())
+  }
+
+  fn assert_parse_error(e: &str, span: Range<usize>, error: &str) {
+    let err = parse(e).unwrap_err();
+    assert_eq!(err.span(), Some(span), "parsing {}", e);
+    assert_eq!(err.to_string(), error, "parsing {}", e);
+  }
+
+  #[test]
+  fn test_parse_errors() {
+    let leaf = "expected a number, a variable, a function, '(' or '-'";
+    assert_parse_error("+a", 0..1, &format!("Parse Error at position 0: unexpected '+', {}", leaf));
+    assert_parse_error("a*", 2..2, &format!("Parse Error at position 2: unexpected end of expression, {}", leaf));
+    assert_parse_error("a*/b", 2..3, &format!("Parse Error at position 2: unexpected '/', {}", leaf));
+    assert_parse_error("(a+b", 0..1, "Parse Error at position 0: '(' is never closed, expected ')'");
+    assert_parse_error("a+b)", 3..4, "Parse Error at position 3: unmatched ')'");
+    assert_parse_error("(a b)", 3..4, "Parse Error at position 3: unexpected 'b', expected an operator or ')'");
+    assert_parse_error("a+b$", 3..4,
+                       "Parse Error at position 3: unexpected '$', expected an operator or the end of the expression");
+    assert_parse_error("2*foo(x)", 2..5,
+                       "Parse Error at position 2: unknown function 'foo', expected sin, cos, tan, exp, ln, sqrt or abs");
+  }
+
+  #[test]
+  fn test_precedence_and_associativity() -> Result<(), ParseError> {
+    for (e, explicit) in [
+      ("a-b-c", "(a-b)-c"), ("a/b/c", "(a/b)/c"), ("a/b*c", "(a/b)*c"), ("a-b+c", "(a-b)+c"),
+      ("a+b*c^d", "a+(b*(c^d))"), ("a^b^c", "a^(b^c)"), ("x^(1/2)^2", "x^((1/2)^2)"), ("sin(x)^2", "(sin(x))^2"),
+      ("-x", "(-1)*x"), ("-x^2", "(-1)*(x^2)"), ("-2^2", "(-1)*(2^2)"), ("-a^b^c", "(-1)*(a^(b^c))"),
+      ("-(a+b)", "(-1)*(a+b)"), ("-2*x", "(-2)*x"), ("--3", "3"), ("a*-b", "a*((-1)*b)"), ("a--b", "a-((-1)*b)"),
+      ("a/-b*c", "(a/((-1)*b))*c"), ("2^-x", "2^((-1)*x)"), ("a^-b^c", "a^((-1)*(b^c))"), ("-[1/2]", "[-1/2]"),
+    ] {
+      assert_eq!(parse(e)?, parse(explicit)?, "{} should be {}", e, explicit);
+    }
+    Ok(())
+  }
+
+  fn assert_parses_with(options: &ParseOptions, e: &str, explicit: &str) -> Result<(), ParseError> {
+    assert_eq!(parse_with(e, options)?, parse(explicit)?, "{} should be {}", e, explicit);
+    Ok(())
+  }
+
+  #[test]
+  fn test_implicit_multiplication() -> Result<(), ParseError> {
+    let options = ParseOptions{implicit_multiplication: true, ..ParseOptions::default()};
+    assert_parses_with(&options, "2x", "2*x")?;
+    assert_parses_with(&options, "3(a+b)", "3*(a+b)")?;
+    assert_parses_with(&options, "(a+b)(a-b)", "(a+b)*(a-b)")?;
+    assert_parses_with(&options, "2x^2 y", "2*x^2*y")?;
+    assert_parses_with(&options, "-2x", "(-2)*x")?;
+    assert_parses_with(&options, "a/2x", "a/2*x")?;
+    assert_parses_with(&options, "x(a+b) - sin(x)(1/2)", "x*(a+b)-sin(x)*(1/2)")?;
+    // words are still one variable
+    assert_parses_with(&options, "2rate", "2*rate")?;
+    // and without implicit multiplication, operands side by side are an error
+    assert_parse_error("2x", 1..2, "Parse Error at position 1: unexpected 'x', expected an operator or the end of the expression");
+    Ok(())
+  }
+
+  #[test]
+  fn test_single_letter_identifiers() -> Result<(), ParseError> {
+    let options = ParseOptions{implicit_multiplication: true, identifiers: Identifiers::SingleLetters};
+    assert_parses_with(&options, "ab", "a*b")?;
+    assert_parses_with(&options, "ab^2", "a*b^2")?;
+    assert_parses_with(&options, "2xy", "2*x*y")?;
+    assert_parses_with(&options, "sin(x)", "sin(x)")?;
+    assert_parses_with(&options, "xsin(y)cos(z)", "x*sin(y)*cos(z)")?;
+    assert_parses_with(&options, "x_1y", "x_1y")?;
+    // a function name is only whole if it's called
+    assert_parses_with(&options, "sin x", "s*i*n*x")?;
+    let err = parse_with("ab", &ParseOptions{identifiers: Identifiers::SingleLetters, ..ParseOptions::default()}).unwrap_err();
+    assert_eq!(err.span, Some(1..2));
+    Ok(())
+  }
+
+  #[test]
+  fn test_whitespace_and_comments() -> Result<(), ParseError> {
+    assert_eq!(parse(" a + b *\tsin ( x ) ")?, parse("a+b*sin(x)")?);
+    assert_eq!(parse("x^2 # squared\n + ( 1 / 2 )")?, parse("x^2+(1/2)")?);
+    Ok(())
+  }
+
+  #[test]
+  fn test_parse_large_batch() -> Result<(), ParseError> {
+    // a megabyte of expressions, one per line
+    let line = "x_1*(y+12)^2 - sin(z)/(3/4) + ln(abs(w))  # a comment\n";
+    let batch = line.repeat((1 << 20) / line.len() + 1);
+    let mut parsed = 0;
+    for e in batch.lines() {
+      parse(e)?;
+      parsed += 1;
+    }
+    assert_eq!(parsed, batch.len() / line.len());
+    Ok(())
+  }
+
+  #[test]
+  fn test_parse_large_expression() -> Result<(), ParseError> {
+    // a megabyte in one expression, as deep as it is wide
+    let mut e = String::from("x_1*(y+12)^2");
+    for operator in ["+", "*", "-", "/"] {
+      e = format!("({})", vec![e.as_str(); 18].join(operator));
+    }
+    assert!(e.len() > 1 << 20);
+    parse(&e)?;
+    Ok(())
+  }
+
+  #[test]
+  fn test_too_deep() {
+    let error = |e: &str| parse(e).unwrap_err().to_string();
+    let nesting = "Parse Error at position 128: parentheses, functions and operators are nested more than 128 deep";
+    let nested = format!("{}x{}", "(".repeat(2000), ")".repeat(2000));
+    assert_eq!(error(&nested), nesting);
+    let negated = format!("{}x", "-".repeat(300));
+    assert_eq!(error(&negated), nesting);
+    let deep = format!("{}x{}", "sin(".repeat(120), ")".repeat(120));
+    assert!(parse(&deep).is_ok());
+    // a sum nests operators in the tree, but not in the parser
+    assert!(parse(&vec!["x"; 1000].join("+")).is_ok());
+    let flat = vec!["x"; 100_000].join("+");
+    assert_eq!(error(&flat), "Parse Error at position 1999: expression is more than 1000 operations deep");
+  }
+
+  #[test]
+  fn test_bindings_with_options() -> Result<(), ParseError> {
+    let options = ParseOptions { implicit_multiplication: true, identifiers: Identifiers::SingleLetters };
+    let bindings = parse_bindings("x = 3y, ab = 2", |e| parse_with(e, &options))?;
+    assert_eq!(bindings[&var!("x")], parse("3*y")?);
+    assert_eq!(bindings[&parse("a*b")?], c!(2));
+    Ok(())
+  }
+
+  #[test]
+  fn test_binding_errors_point_into_bindings() {
+    let err = parse_bindings("x = 1, y = 2*", parse).unwrap_err();
+    assert_eq!(err.span(), Some(13..13));
+    assert_eq!(err.expected(), LEAF);
+  }
+
+  #[test]
+  fn test_diagnostic() {
+    let e = "x^2+sin(x))";
+    assert_eq!(parse(e).unwrap_err().diagnostic(e), "Parse Error at position 10: unmatched ')'\n  x^2+sin(x))\n            ^");
+  }
 }
@@ -0,0 +1,90 @@
+use crate::parser::{Expression, Rational};
+use num_traits::{One, Signed};
+use std::ops::Deref;
+
+impl Expression {
+  /// An equal expression that reads more naturally, undoing the forms the rules prefer:
+  /// a+(-1)*b becomes a-b, a+(-2)*b becomes a-2*b, a+(-3) becomes a-3, a*b^(-1) becomes a/b,
+  /// and the constant [1/2] becomes 1/2.
+  pub fn sugared(&self) -> Expression {
+    // each form is sugared by its own function, so each level of a deep expression takes little of the stack
+    match self {
+      Expression::Constant(c) if !c.is_integer() => sugared_fraction(c),
+      Expression::Constant(_) | Expression::Variable(_) => self.clone(),
+      Expression::Apply(func, a) => func.apply(a.sugared()),
+      Expression::Sum(a, b) => sugared_sum(a.sugared(), b),
+      Expression::Product(a, b) => sugared_product(a, b),
+      Expression::Difference(a, b) => a.sugared() - b.sugared(),
+      Expression::Quotient(a, b) => a.sugared() / b.sugared(),
+      Expression::Power(a, b) => a.sugared() ^ b.sugared(),
+    }
+  }
+
+  /// The sugared expression, for people to read. Unlike Display, parsing it back gives an expression
+  /// that is equal, but not necessarily the same.
+  pub fn pretty(&self) -> String {
+    self.sugared().to_string()
+  }
+}
+
+// The fraction as a quotient of integers, e.g. [1/2] as 1/2.
+fn sugared_fraction(c: &Rational) -> Expression {
+  Expression::Constant(Rational::from_integer(c.numer().clone())) /
+    Expression::Constant(Rational::from_integer(c.denom().clone()))
+}
+
+// a+b, with a already sugared.
+fn sugared_sum(a: Expression, b: &Expression) -> Expression {
+  match negated(b) {
+    Some(b) => a - b,
+    None => a + b.sugared(),
+  }
+}
+
+// -b sugared, if b is a negative constant or a negative constant times something.
+fn negated(b: &Expression) -> Option<Expression> {
+  match b {
+    Expression::Product(c, d) => match c.deref() {
+      Expression::Constant(c) if c.is_negative() => {
+        let c = -c;
+        Some(if c.is_one() { d.sugared() } else { Expression::Constant(c).sugared() * d.sugared() })
+      },
+      _ => None,
+    },
+    Expression::Constant(c) if c.is_negative() => Some(Expression::Constant(-c).sugared()),
+    _ => None,
+  }
+}
+
+fn sugared_product(a: &Expression, b: &Expression) -> Expression {
+  match (a, b) {
+    (_, Expression::Power(d, e)) if is_minus_one(e) => a.sugared() / d.sugared(),
+    (Expression::Power(d, e), _) if is_minus_one(e) => b.sugared() / d.sugared(),
+    _ => a.sugared() * b.sugared(),
+  }
+}
+
+fn is_minus_one(e: &Expression) -> bool {
+  matches!(e, Expression::Constant(c) if *c == -Rational::one())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::parser::{parse, ParseError};
+
+  fn assert_pretty(e: &str, pretty: &str) -> Result<(), ParseError> {
+    assert_eq!(parse(e)?.pretty(), pretty);
+    Ok(())
+  }
+
+  #[test]
+  fn test_pretty() -> Result<(), ParseError> {
+    assert_pretty("a+(-1)*b", "a-b")?;
+    assert_pretty("a+(-2)*(b+c)", "a-2*(b+c)")?;
+    assert_pretty("x+(-3)", "x-3")?;
+    assert_pretty("a*b^(-1)", "a/b")?;
+    assert_pretty("b^(-1)*(a+c)", "(a+c)/b")?;
+    assert_pretty("sin(a+(-1)*b)^2", "sin(a-b)^2")?;
+    assert_pretty("x^[1/2]+[-5/6]", "x^(1/2)-5/6")
+  }
+}
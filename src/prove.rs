@@ -0,0 +1,217 @@
+use crate::assumptions::Assumptions;
+use crate::parser::Expression;
+use crate::rules::Rules;
+use crate::measure::{measure, max_measure, SearchConfig, StopReason};
+use crate::transformation_graph::{self, Derivation, Graph};
+use crate::tree_transform::{self, Equivalence, Step, simplify_via_forward_transform};
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+use std::time::Instant;
+
+// Unless the config has a node or time budget, a proof that isn't found gives up after this many expressions.
+const DEFAULT_NODE_BUDGET: usize = 20_000;
+
+/// The result of trying to show that two expressions are equal.
+#[derive(Debug)]
+pub enum Proof {
+  /// How to rewrite the left-hand side into the right-hand side.
+  Proven(Derivation),
+  /// The search explored this many expressions without finding a proof, and stopped for stop_reason.
+  NotProven { explored: usize, stop_reason: StopReason },
+}
+
+impl fmt::Display for Proof {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Proof::Proven(derivation) => write!(f, "Proven:\n{}", derivation),
+      Proof::NotProven { explored, stop_reason: StopReason::Exhausted } =>
+        write!(f, "Not proven, the rules reach nothing else (explored {} expressions)", explored),
+      Proof::NotProven { explored, stop_reason: StopReason::Timeout } =>
+        write!(f, "Not proven before the timeout (explored {} expressions)", explored),
+      Proof::NotProven { explored, stop_reason: StopReason::Cancelled } =>
+        write!(f, "Not proven, cancelled (explored {} expressions)", explored),
+      Proof::NotProven { explored, .. } =>
+        write!(f, "Not proven within budget (explored {} expressions)", explored),
+    }
+  }
+}
+
+// One direction of the bidirectional search: a graph of everything reached from one side.
+struct Search<'b> {
+  graph: Graph<'b>,
+  root: Rc<Expression>,
+  to_transform: VecDeque<Rc<Expression>>,
+}
+
+impl<'b> Search<'b> {
+  fn new(root: Rc<Expression>) -> Search<'b> {
+    let mut to_transform = VecDeque::new();
+    to_transform.push_back(Rc::clone(&root));
+    Search { graph: transformation_graph::create_graph(Rc::clone(&root)), root, to_transform }
+  }
+
+  // Transforms the next expression in the queue, stopping at the first result the other search has reached.
+  fn expand(&mut self, equivalences: &'b [Equivalence], simple_equivalences: &'b [Equivalence],
+            known: &Assumptions, max_measure: i32, other: &Graph) -> Option<Rc<Expression>> {
+    let e = self.to_transform.pop_front()?;
+    // Everything else in the graph has been through the simple transformations already.
+    let simple = if Rc::ptr_eq(&e, &self.root) { simple_equivalences } else { &[] };
+    for equivalence in simple.iter().chain(equivalences.iter()) {
+      for rewrite in tree_transform::transform(e.as_ref(), equivalence, known).into_iter() {
+        let step = Step::new(rewrite, equivalence);
+        let (transformed, simple_steps) = simplify_via_forward_transform(step.exp.clone(), simple_equivalences, known);
+        if measure(&transformed) >= max_measure { continue; }
+        let mut steps = vec![step];
+        steps.extend(simple_steps);
+        let transformed = Rc::new(transformed);
+        if self.graph.add_node(Rc::clone(&e), Rc::clone(&transformed), equivalence, steps) {
+          if other.contains(transformed.as_ref()) {
+            return Some(transformed);
+          }
+          self.to_transform.push_back(transformed);
+        }
+      }
+    }
+    None
+  }
+}
+
+/// Searches for a chain of rewrites from lhs to rhs, growing a transformation graph from each end
+/// until they meet, or until a budget in config is spent. The graphs together may hold config.max_nodes expressions,
+/// or 20000 if config has neither a node nor a time budget. The search strategy and depth limit don't apply.
+pub fn prove_equivalent(lhs: Expression, rhs: Expression, rules: &Rules, known: &Assumptions, config: &SearchConfig)
+                        -> Proof {
+  let started = Instant::now();
+  let max_nodes = match config.max_nodes {
+    None if config.timeout.is_none() => Some(DEFAULT_NODE_BUDGET),
+    max_nodes => max_nodes,
+  };
+  if lhs == rhs {
+    return Proof::Proven(Derivation { start: lhs, steps: Vec::new() });
+  }
+  // Expressions much bigger than both sides are unlikely to be on the shortest proof.
+  let max_measure = max_measure(measure(&lhs).max(measure(&rhs)));
+  let mut forwards = Search::new(Rc::new(lhs));
+  let mut backwards = Search::new(Rc::new(rhs));
+  loop {
+    let explored = forwards.graph.size() + backwards.graph.size();
+    let stop_reason = if forwards.to_transform.is_empty() && backwards.to_transform.is_empty() {
+      Some(StopReason::Exhausted)
+    } else if config.cancellation.is_cancelled() {
+      Some(StopReason::Cancelled)
+    } else if config.timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+      Some(StopReason::Timeout)
+    } else if max_nodes.is_some_and(|max_nodes| explored > max_nodes) {
+      Some(StopReason::NodeLimit)
+    } else {
+      None
+    };
+    if let Some(stop_reason) = stop_reason {
+      return Proof::NotProven { explored, stop_reason };
+    }
+    // Grow whichever side has the smaller frontier, so neither explodes on its own.
+    let from_lhs = backwards.to_transform.is_empty() ||
+      (!forwards.to_transform.is_empty() && forwards.to_transform.len() <= backwards.to_transform.len());
+    let meeting = if from_lhs {
+      forwards.expand(&rules.equivalences, &rules.simple, known, max_measure, &backwards.graph)
+    } else {
+      backwards.expand(&rules.equivalences, &rules.simple, known, max_measure, &forwards.graph)
+    };
+    if let Some(meeting) = meeting {
+      let to_meeting = forwards.graph.derivation(meeting.as_ref()).unwrap();
+      let from_meeting = backwards.graph.derivation(meeting.as_ref()).unwrap().reversed();
+      return Proof::Proven(to_meeting.then(from_meeting));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::{parse, ParseError};
+  use std::time::Duration;
+
+  fn assert_proven(lhs: &str, rhs: &str) -> Result<(), ParseError> {
+    let lhs = parse(lhs)?;
+    let rhs = parse(rhs)?;
+    match prove_equivalent(lhs.clone(), rhs.clone(), &Rules::default(), &Assumptions::new(), &SearchConfig::default()) {
+      Proof::Proven(derivation) => {
+        assert_eq!(derivation.start, lhs);
+        assert_eq!(derivation.steps.last().unwrap().exp, rhs);
+        // every step changes something
+        let mut previous = &derivation.start;
+        for step in derivation.steps.iter() {
+          assert_ne!(&step.exp, previous, "{}", derivation);
+          previous = &step.exp;
+        }
+      },
+      not_proven => panic!("{}", not_proven),
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_prove_square() -> Result<(), ParseError> {
+    assert_proven("(a+b)^2", "a^2+2*a*b+b^2")
+  }
+
+  #[test]
+  fn test_prove_difference_of_squares() -> Result<(), ParseError> {
+    assert_proven("(a+b)*(a-b)", "a*a-b*b")
+  }
+
+  #[test]
+  fn test_prove_commuted() -> Result<(), ParseError> {
+    assert_proven("a*(b+c)", "c*a+a*b")
+  }
+
+  #[test]
+  fn test_prove_by_simple_rule() -> Result<(), ParseError> {
+    // the simple rules apply to the start too, instead of only after some other rewrite
+    match prove_equivalent(parse("x*0")?, parse("0")?, &Rules::default(), &Assumptions::new(), &SearchConfig::default()) {
+      Proof::Proven(derivation) => assert_eq!(derivation.steps.len(), 1, "{}", derivation),
+      not_proven => panic!("{}", not_proven),
+    }
+    assert_proven("x*0", "0")
+  }
+
+  #[test]
+  fn test_not_proven() -> Result<(), ParseError> {
+    match prove_equivalent(parse("a+b")?, parse("a*b")?, &Rules::default(), &Assumptions::new(),
+                           &SearchConfig::default()) {
+      Proof::NotProven { explored, stop_reason } => {
+        assert!(explored > 0);
+        assert_eq!(stop_reason, StopReason::Exhausted);
+      },
+      Proof::Proven(derivation) => panic!("bogus proof:\n{}", derivation),
+    }
+    let config = SearchConfig { max_nodes: Some(500), ..SearchConfig::default() };
+    let (lhs, rhs) = (parse("(a+b)^3")?, parse("a^3+3*a^2*b+3*a*b^2+b^3+1")?);
+    match prove_equivalent(lhs, rhs, &Rules::default(), &Assumptions::new(), &config) {
+      Proof::NotProven { explored, stop_reason } => {
+        assert!(explored > 500 && explored < 1000, "{}", explored);
+        assert_eq!(stop_reason, StopReason::NodeLimit);
+      },
+      Proof::Proven(derivation) => panic!("bogus proof:\n{}", derivation),
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_not_proven_in_time() -> Result<(), ParseError> {
+    let (lhs, rhs) = (parse("(a+b)^3")?, parse("a^3+3*a^2*b+3*a*b^2+b^3+1")?);
+    let config = SearchConfig { timeout: Some(Duration::from_millis(0)), ..SearchConfig::default() };
+    match prove_equivalent(lhs.clone(), rhs.clone(), &Rules::default(), &Assumptions::new(), &config) {
+      Proof::NotProven { stop_reason, .. } => assert_eq!(stop_reason, StopReason::Timeout),
+      Proof::Proven(derivation) => panic!("bogus proof:\n{}", derivation),
+    }
+    let config = SearchConfig::default();
+    config.cancellation.cancel();
+    match prove_equivalent(lhs, rhs, &Rules::default(), &Assumptions::new(), &config) {
+      Proof::NotProven { stop_reason, .. } => assert_eq!(stop_reason, StopReason::Cancelled),
+      Proof::Proven(derivation) => panic!("bogus proof:\n{}", derivation),
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,195 @@
+use crate::condition::Condition;
+use crate::parser::{self, Expression};
+use crate::tree_transform::{self, Equivalence};
+use std::fmt;
+use std::fs;
+
+/// The rules shipped with the program, used unless others are loaded with `--rules`.
+const DEFAULT_RULES: &str = include_str!("../rules/default.rules");
+
+/// The equivalences to search with, and the simple ones applied after each of them.
+pub struct Rules {
+  pub equivalences: Vec<Equivalence>,
+  pub simple: Vec<Equivalence>,
+}
+
+impl Default for Rules {
+  fn default() -> Self {
+    Rules::parse(DEFAULT_RULES).unwrap()
+  }
+}
+
+#[derive(Debug)]
+pub struct RuleError {
+  // None if the file couldn't be read at all
+  line: Option<usize>,
+  msg: String,
+}
+
+impl fmt::Display for RuleError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.line {
+      Some(line) => write!(f, "Rule Error on line {}: {}", line, self.msg),
+      None => write!(f, "Rule Error: {}", self.msg),
+    }
+  }
+}
+
+impl Rules {
+  /// Reads a rule file. See rules/default.rules for the format.
+  pub fn load(path: &str) -> Result<Rules, RuleError> {
+    let text = fs::read_to_string(path)
+      .map_err(|err| RuleError { line: None, msg: format!("can't read '{}': {}", path, err) })?;
+    Rules::parse(&text)
+  }
+
+  pub fn parse(text: &str) -> Result<Rules, RuleError> {
+    let mut rules = Rules { equivalences: Vec::new(), simple: Vec::new() };
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') { continue; }
+      let (equivalence, simple) = parse_rule(line).map_err(|msg| RuleError { line: Some(i + 1), msg })?;
+      if simple {
+        rules.simple.push(equivalence);
+      } else {
+        rules.equivalences.push(equivalence);
+      }
+    }
+    Ok(rules)
+  }
+}
+
+// The rule on the line, and whether it is simple.
+fn parse_rule(line: &str) -> Result<(Equivalence, bool), String> {
+  // the rule itself, then sections of conditions, each starting with its keyword
+  let mut sections: Vec<(&str, Vec<&str>)> = vec![("", Vec::new())];
+  for word in line.split_whitespace() {
+    match word {
+      "if" | "requires" => sections.push((word, Vec::new())),
+      _ => sections.last_mut().unwrap().1.push(word),
+    }
+  }
+  let mut rule = sections.remove(0).1;
+  let simple = rule.last() == Some(&"[simple]");
+  if simple {
+    rule.pop();
+  }
+  let mut equivalence = match rule.as_slice() {
+    [name] => tree_transform::method_equivalence(name).ok_or(format!("unknown rule '{}'", name))?,
+    [before, arrow, after] => {
+      let forwards_only = match *arrow {
+        "->" => true,
+        "<->" => false,
+        _ => return Err(format!("expected -> or <->, got '{}'", arrow)),
+      };
+      if simple && !forwards_only {
+        return Err(String::from("simple rules can only go forwards, with ->"));
+      }
+      Equivalence {
+        before: parse_expression(before)?,
+        after: parse_expression(after)?,
+        forwards_only,
+        ..Default::default()
+      }
+    },
+    _ => return Err(String::from("expected 'before -> after', 'before <-> after' or the name of a rule")),
+  };
+  for (keyword, words) in sections {
+    let conditions = parse_conditions(&words.join(" "))?;
+    match keyword {
+      "if" => equivalence.conditions.extend(conditions),
+      _ => equivalence.requirements.extend(conditions),
+    }
+  }
+  Ok((equivalence, simple))
+}
+
+fn parse_expression(e: &str) -> Result<Expression, String> {
+  parser::parse(e).map_err(|err| format!("in '{}': {}", e, err))
+}
+
+// e.g. `(c is an integer) or (a >= 0), b > 0`
+fn parse_conditions(text: &str) -> Result<Vec<Condition>, String> {
+  if text.is_empty() {
+    return Err(String::from("expected conditions"));
+  }
+  text.split(',').map(|condition| {
+    let mut alternatives = condition.split(" or ")
+      .map(|alternative| {
+        let alternative = alternative.trim();
+        // every condition ends in 0, integer or constant, so a closing parenthesis is around the whole of it
+        let alternative = match alternative.strip_prefix('(').and_then(|a| a.strip_suffix(')')) {
+          Some(inner) => inner.trim(),
+          None => alternative,
+        };
+        parse_condition(alternative)
+      })
+      .collect::<Result<Vec<Condition>, String>>()?;
+    Ok(if alternatives.len() == 1 { alternatives.pop().unwrap() } else { Condition::Any(alternatives) })
+  }).collect()
+}
+
+// how a condition ends, and the condition it is
+type Relation = (&'static str, fn(Expression) -> Condition);
+
+fn parse_condition(text: &str) -> Result<Condition, String> {
+  let relations: [Relation; 5] = [
+    (" != 0", Condition::NonZero),
+    (" >= 0", Condition::NonNegative),
+    (" > 0", Condition::Positive),
+    (" is an integer", Condition::Integer),
+    (" is constant", Condition::Constant),
+  ];
+  for (relation, condition) in relations.iter() {
+    if let Some(subject) = text.strip_suffix(relation) {
+      return Ok(condition(parse_expression(subject.trim())?));
+    }
+  }
+  Err(format!("expected a condition like 'a != 0', 'a > 0', 'a >= 0', 'a is an integer' or 'a is constant', got '{}'",
+              text))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::var;
+
+  #[test]
+  fn test_default_rules() {
+    let rules = Rules::default();
+    assert!(rules.simple.iter().all(|equivalence| equivalence.forwards_only));
+    let names: Vec<&str> = rules.equivalences.iter()
+      .filter(|equivalence| equivalence.method.is_some())
+      .map(|equivalence| equivalence.method_name.as_str())
+      .collect();
+    assert_eq!(names, vec!["eval_const", "group_repeated", "split_repeated_operation"]);
+  }
+
+  #[test]
+  fn test_parse_rules() {
+    let rules = Rules::parse("\
+      # comment\n\
+      a*(b+c) <-> a*b+a*c\n\
+      \n\
+      a/a -> 1 [simple] if a != 0\n\
+      (a^b)^c -> a^(b*c) [simple] requires (c is an integer) or (a > 0)\n\
+      split_constants\n").unwrap();
+    assert_eq!(rules.equivalences.len(), 2);
+    assert!(!rules.equivalences[0].forwards_only);
+    assert_eq!(rules.simple.len(), 2);
+    assert_eq!(rules.simple[0].conditions, vec![Condition::NonZero(var!("a"))]);
+    assert_eq!(rules.simple[1].requirements,
+               vec![Condition::Any(vec![Condition::Integer(var!("c")), Condition::Positive(var!("a"))])]);
+  }
+
+  #[test]
+  fn test_rule_errors() {
+    for (text, line) in [("a+b <-> b+a\na => b", 2), ("\n\na <-> a+0 [simple]", 3), ("b*a -> a*b if a < 0", 1),
+                         ("no_such_rule", 1), ("a) <-> b", 1)] {
+      match Rules::parse(text) {
+        Err(err) => assert_eq!(err.line, Some(line), "{}", err),
+        Ok(_) => panic!("parsed '{}'", text),
+      }
+    }
+  }
+}
@@ -0,0 +1,156 @@
+use crate::assumptions::{Assumptions, Domain};
+use crate::cost::{CostFunction, Weighted};
+use crate::derivative::simplified_derivative;
+use crate::egraph::{self, SaturationResult};
+use crate::measure::{self, Observer, SearchConfig, Silent, SimplifyResult};
+use crate::parser::Expression;
+use crate::prove::{prove_equivalent, Proof};
+use crate::rules::Rules;
+
+/// Simplifies expressions with a set of rules, given what is known about their variables.
+/// Built up from the defaults, e.g. `Simplifier::new().assume("x", Domain::Positive)`.
+pub struct Simplifier {
+  rules: Rules,
+  known: Assumptions,
+  cost: Box<dyn CostFunction>,
+  config: SearchConfig,
+}
+
+impl Default for Simplifier {
+  fn default() -> Self {
+    Simplifier::new()
+  }
+}
+
+impl Simplifier {
+  /// The default rules, with nothing known about any variable.
+  pub fn new() -> Simplifier {
+    Simplifier {
+      rules: Rules::default(),
+      known: Assumptions::new(),
+      cost: Box::new(Weighted::default()),
+      config: SearchConfig::default(),
+    }
+  }
+
+  /// Simplifies with these rules instead of the default ones.
+  pub fn rules(mut self, rules: Rules) -> Simplifier {
+    self.rules = rules;
+    self
+  }
+
+  /// Simplifies knowing this about the variable, e.g. that x is positive, so sqrt(x^2) is x.
+  pub fn assume(mut self, variable: &str, domain: Domain) -> Simplifier {
+    self.known.assume(variable, domain);
+    self
+  }
+
+  /// Simplifies knowing all of these.
+  pub fn assumptions(mut self, known: Assumptions) -> Simplifier {
+    self.known = known;
+    self
+  }
+
+  /// Minimizes this instead of `measure`, e.g. `PreferFactored` for products of sums.
+  pub fn cost(mut self, cost: Box<dyn CostFunction>) -> Simplifier {
+    self.cost = cost;
+    self
+  }
+
+  /// Stops searching when any of these budgets is spent, instead of only when there's nothing left to search.
+  pub fn search(mut self, config: SearchConfig) -> Simplifier {
+    self.config = config;
+    self
+  }
+
+  /// Searches the rewrites of e for the smallest one, or the smallest found before a budget ran out.
+  pub fn simplify(&self, e: &Expression) -> SimplifyResult {
+    self.simplify_observed(e, &mut Silent)
+  }
+
+  /// Like `simplify`, telling observer how the search is going.
+  pub fn simplify_observed(&self, e: &Expression, observer: &mut dyn Observer) -> SimplifyResult {
+    measure::find_min_equivalent_expr(e.clone(), &self.rules, &self.known, self.cost.as_ref(), &self.config,
+                                      observer)
+  }
+
+  /// Simplifies by equality saturation, which can't say how it got there, and ignores the search budgets.
+  pub fn simplify_by_egraph(&self, e: &Expression) -> SaturationResult {
+    egraph::find_min_equivalent_expr(e.clone(), &self.rules, &self.known, self.cost.as_ref())
+  }
+
+  /// The derivative of e with respect to var, simplified like `simplify` would, but within 2000 expressions
+  /// unless a node or time budget was set with `search`.
+  pub fn derivative(&self, e: &Expression, var: &str) -> Expression {
+    simplified_derivative(e, var, &self.rules, &self.known, self.cost.as_ref(), &self.config)
+  }
+
+  /// Searches for rewrites of lhs into rhs, giving up once the node or time budget set with `search` is spent,
+  /// or after 20000 expressions if neither was set.
+  pub fn prove(&self, lhs: &Expression, rhs: &Expression) -> Proof {
+    prove_equivalent(lhs.clone(), rhs.clone(), &self.rules, &self.known, &self.config)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cost::PreferExpanded;
+  use crate::measure::StopReason;
+  use crate::parser::{parse, ParseError};
+  use crate::var;
+
+  #[test]
+  fn test_simplify() -> Result<(), ParseError> {
+    let e = parse("sqrt(x^2)")?;
+    assert_eq!(Simplifier::new().simplify(&e).expression, parse("abs(x)")?);
+    let result = Simplifier::new().assume("x", Domain::Positive).simplify(&e);
+    assert_eq!(result.expression, var!("x"));
+    assert_eq!(result.derivation.start, e);
+    Ok(())
+  }
+
+  #[test]
+  fn test_rules() -> Result<(), ParseError> {
+    // without any rules, nothing can be simplified
+    let e = parse("x*1")?;
+    assert_eq!(Simplifier::new().rules(Rules::parse("").unwrap()).simplify(&e).expression, e);
+    Ok(())
+  }
+
+  #[test]
+  fn test_cost() -> Result<(), ParseError> {
+    let e = parse("x*(y+z)")?;
+    assert_eq!(Simplifier::new().simplify(&e).expression, e);
+    let result = Simplifier::new().cost(Box::new(PreferExpanded)).simplify(&e);
+    assert_eq!(result.expression, parse("x*y+x*z")?);
+    assert_eq!(result.measure, PreferExpanded.cost(&result.expression));
+    Ok(())
+  }
+
+  #[test]
+  fn test_search() -> Result<(), ParseError> {
+    let simplifier = Simplifier::new().search(SearchConfig { max_depth: Some(0), ..SearchConfig::default() });
+    let result = simplifier.simplify(&parse("x*y/x")?);
+    assert_eq!(result.stop_reason, StopReason::DepthLimit);
+    assert_eq!(result.expression, parse("x*y/x")?);
+    Ok(())
+  }
+
+  #[test]
+  fn test_long_sum() -> Result<(), ParseError> {
+    // as deep as parsing allows, so every rule has to look all the way down it without overflowing the stack
+    let e = parse(&vec!["x"; 1000].join("+"))?;
+    let simplifier = Simplifier::new().search(SearchConfig { max_nodes: Some(20), ..SearchConfig::default() });
+    let result = simplifier.simplify(&e);
+    assert!(result.measure <= Weighted::default().cost(&e));
+    assert_eq!(result.stop_reason, StopReason::NodeLimit);
+    Ok(())
+  }
+
+  #[test]
+  fn test_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Simplifier>();
+  }
+}
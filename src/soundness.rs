@@ -0,0 +1,188 @@
+use crate::assumptions::{Assumptions, Domain};
+use crate::condition::Condition;
+use crate::parser::Expression;
+use crate::transformation_graph::Derivation;
+use crate::tree_transform::Equivalence;
+use std::collections::HashMap;
+use std::fmt;
+
+// Checks rules and derivations by evaluating both sides at random points.
+// Passing doesn't prove a rule sound, but a rule that fails is certainly wrong.
+
+/// How many points each rule or step is evaluated at.
+pub const SAMPLES: usize = 100;
+// Points where a side is undefined or a condition fails are skipped, so give up after this many tries per sample.
+const ATTEMPTS_PER_SAMPLE: usize = 20;
+const TOLERANCE: f64 = 1e-6;
+
+/// Values for the variables at which two expressions that should be equal aren't.
+#[derive(Debug)]
+pub struct Counterexample {
+  /// The rule or step that failed.
+  pub what: String,
+  pub values: Vec<(String, f64)>,
+  pub lhs: f64,
+  pub rhs: f64,
+}
+
+impl fmt::Display for Counterexample {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let values: Vec<String> = self.values.iter().map(|(v, x)| format!("{}={}", v, x)).collect();
+    write!(f, "{} is wrong at {}: {} != {}", self.what, values.join(", "), self.lhs, self.rhs)
+  }
+}
+
+// xorshift64*, so that checks are reproducible without pulling in a crate for it.
+struct Rng(u64);
+
+impl Rng {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+
+  // uniform in [0, 1)
+  fn next_f64(&mut self) -> f64 {
+    (self.next() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  // Small integers often enough to satisfy `is an integer` conditions, and to hit 0 and 1,
+  // and otherwise any real in [-4, 4].
+  fn value(&mut self) -> f64 {
+    if self.next().is_multiple_of(3) {
+      (self.next() % 11) as f64 - 5.
+    } else {
+      self.next_f64() * 8. - 4.
+    }
+  }
+}
+
+// Whether the condition holds at the point. Undefined subjects don't satisfy anything.
+fn holds(condition: &Condition, values: &HashMap<String, f64>) -> bool {
+  let at = |e: &Expression, test: fn(f64) -> bool| e.evaluate(values).is_ok_and(test);
+  match condition {
+    Condition::NonZero(e) => at(e, |x| x != 0.),
+    Condition::Positive(e) => at(e, |x| x > 0.),
+    Condition::NonNegative(e) => at(e, |x| x >= 0.),
+    Condition::Integer(e) => at(e, |x| x.fract() == 0.),
+    Condition::Constant(e) => e.free_variables().is_empty(),
+    Condition::Any(conditions) => conditions.iter().any(|c| holds(c, values)),
+  }
+}
+
+fn agree(x: f64, y: f64) -> bool {
+  (x - y).abs() <= TOLERANCE * x.abs().max(y.abs()).max(1.)
+}
+
+// Evaluates lhs and rhs at up to `samples` random points where the conditions and the assumptions hold,
+// and both sides are defined. Returns how many points were checked.
+fn compare(what: &dyn fmt::Display, lhs: &Expression, rhs: &Expression, conditions: &[Condition],
+           known: &Assumptions, samples: usize, rng: &mut Rng) -> Result<usize, Counterexample> {
+  let mut vars = lhs.free_variables();
+  vars.extend(rhs.free_variables());
+  let assumed = |values: &HashMap<String, f64>| values.iter().all(|(v, x)|
+    (!known.is(v, Domain::NonZero) || *x != 0.) &&
+    (!known.is(v, Domain::NonNegative) || *x >= 0.) &&
+    (!known.is(v, Domain::Positive) || *x > 0.) &&
+    (!known.is(v, Domain::Integer) || x.fract() == 0.));
+  let mut checked = 0;
+  for _ in 0..samples * ATTEMPTS_PER_SAMPLE {
+    if checked == samples { break; }
+    let values: HashMap<String, f64> = vars.iter().map(|v| (v.clone(), rng.value())).collect();
+    if !assumed(&values) || !conditions.iter().all(|c| holds(c, &values)) { continue; }
+    // rules are allowed to change where an expression is defined, e.g. a*0 = 0
+    let (x, y) = match (lhs.evaluate(&values), rhs.evaluate(&values)) {
+      (Ok(x), Ok(y)) => (x, y),
+      _ => continue,
+    };
+    if !agree(x, y) {
+      return Err(Counterexample {
+        what: what.to_string(),
+        values: vars.iter().map(|v| (v.clone(), values[v])).collect(),
+        lhs: x,
+        rhs: y,
+      });
+    }
+    checked += 1;
+  }
+  Ok(checked)
+}
+
+/// Checks that both sides of a pattern-based equivalence agree wherever its conditions hold.
+/// Returns how many points it was checked at; 0 for equivalences implemented by a closure,
+/// which can only be checked through the derivations that use them.
+pub fn check_equivalence(equiv: &Equivalence, samples: usize) -> Result<usize, Counterexample> {
+  if equiv.method.is_some() {
+    return Ok(0);
+  }
+  let conditions: Vec<Condition> = equiv.conditions.iter().chain(equiv.requirements.iter()).cloned().collect();
+  let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+  compare(equiv, &equiv.before, &equiv.after, &conditions, &Assumptions::new(), samples, &mut rng)
+}
+
+/// Checks that every step of the derivation agrees with the expression before it,
+/// wherever what the step assumed and what is known hold.
+pub fn check_derivation(derivation: &Derivation, known: &Assumptions, samples: usize) -> Result<(), Counterexample> {
+  let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+  let mut prev = &derivation.start;
+  for step in derivation.steps.iter() {
+    compare(step, prev, &step.exp, &step.assumptions, known, samples, &mut rng)?;
+    prev = &step.exp;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cost::Weighted;
+  use crate::measure::{find_min_equivalent_expr, SearchConfig, Silent};
+  use crate::parser::{parse, ParseError};
+  use crate::rules::Rules;
+
+  #[test]
+  fn test_default_rules_are_sound() {
+    let rules = Rules::default();
+    for equiv in rules.equivalences.iter().chain(rules.simple.iter()) {
+      match check_equivalence(equiv, SAMPLES) {
+        Ok(checked) => assert!(equiv.method.is_some() || checked > SAMPLES / 2, "{} checked at {} points", equiv,
+                               checked),
+        Err(counterexample) => panic!("{}", counterexample),
+      }
+    }
+  }
+
+  #[test]
+  fn test_unsound_rules_are_flagged() {
+    let rules = Rules::parse("a+b <-> a*b\nexp(a*b) <-> exp(a)*exp(b)\nsqrt(a^2) -> a [simple]").unwrap();
+    for equiv in rules.equivalences.iter().chain(rules.simple.iter()) {
+      assert!(check_equivalence(equiv, SAMPLES).is_err(), "{}", equiv);
+    }
+  }
+
+  #[test]
+  fn test_derivations_are_sound() -> Result<(), ParseError> {
+    let rules = Rules::default();
+    let mut known = Assumptions::new();
+    known.assume_str("y > 0").unwrap();
+    for e in ["(a*b)/a", "1+2^2*9/6-3", "(a+b)*(a-b)", "a*a*a*a^2", "2*a-a-c", "sqrt(y^2)*exp(ln(y))"] {
+      let derivation = find_min_equivalent_expr(parse(e)?, &rules, &known, &Weighted::default(),
+                                                &SearchConfig::default(), &mut Silent).derivation;
+      if let Err(counterexample) = check_derivation(&derivation, &known, SAMPLES) {
+        panic!("{}", counterexample);
+      }
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_wrong_derivation_is_flagged() -> Result<(), ParseError> {
+    let mut derivation = find_min_equivalent_expr(parse("a+a")?, &Rules::default(), &Assumptions::new(),
+                                                  &Weighted::default(), &SearchConfig::default(), &mut Silent).derivation;
+    derivation.steps.last_mut().unwrap().exp = parse("3*a")?;
+    assert!(check_derivation(&derivation, &Assumptions::new(), SAMPLES).is_err());
+    Ok(())
+  }
+}
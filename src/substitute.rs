@@ -0,0 +1,81 @@
+use crate::parser::Expression;
+use std::collections::{BTreeSet, HashMap};
+
+impl Expression {
+  /// The expression with every subtree that is a key of bindings replaced by its value,
+  /// all at once, so replacements aren't substituted into again.
+  /// Keys can be variables, e.g. `x -> a+b`, or any subexpression, e.g. `x^2 -> y`.
+  /// There are no binders (like the variable of an integral) yet, so nothing can be captured.
+  pub fn substitute(&self, bindings: &HashMap<Expression, Expression>) -> Expression {
+    if let Some(replacement) = bindings.get(self) {
+      return replacement.clone();
+    }
+    match self {
+      Expression::Constant(_) | Expression::Variable(_) => self.clone(),
+      Expression::Sum(a, b) => a.substitute(bindings) + b.substitute(bindings),
+      Expression::Product(a, b) => a.substitute(bindings) * b.substitute(bindings),
+      Expression::Difference(a, b) => a.substitute(bindings) - b.substitute(bindings),
+      Expression::Quotient(a, b) => a.substitute(bindings) / b.substitute(bindings),
+      Expression::Power(a, b) => a.substitute(bindings) ^ b.substitute(bindings),
+      Expression::Apply(func, a) => func.apply(a.substitute(bindings)),
+    }
+  }
+
+  /// The names of the variables in the expression.
+  pub fn free_variables(&self) -> BTreeSet<String> {
+    let mut variables = BTreeSet::new();
+    self.collect_variables(&mut variables);
+    variables
+  }
+
+  fn collect_variables(&self, into: &mut BTreeSet<String>) {
+    match self {
+      Expression::Constant(_) => (),
+      Expression::Variable(v) => { into.insert(v.clone()); },
+      Expression::Sum(a, b) | Expression::Product(a, b) | Expression::Difference(a, b) |
+      Expression::Quotient(a, b) | Expression::Power(a, b) => {
+        a.collect_variables(into);
+        b.collect_variables(into);
+      },
+      Expression::Apply(_, a) => a.collect_variables(into),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::parser::{parse, parse_bindings, ParseError};
+
+  fn assert_substituted(e: &str, bindings: &str, substituted: &str) -> Result<(), ParseError> {
+    assert_eq!(parse(e)?.substitute(&parse_bindings(bindings, parse)?), parse(substituted)?);
+    Ok(())
+  }
+
+  #[test]
+  fn test_substitute_variables() -> Result<(), ParseError> {
+    assert_substituted("x^2+y", "x = a+b", "(a+b)^2+y")?;
+    // simultaneously, so x doesn't become y and then z
+    assert_substituted("x*y", "x = y, y = z", "y*z")?;
+    assert_substituted("sin(x)", "y = 2", "sin(x)")
+  }
+
+  #[test]
+  fn test_substitute_subexpressions() -> Result<(), ParseError> {
+    assert_substituted("x^2+sin(x^2)", "x^2 = u", "u+sin(u)")?;
+    assert_substituted("(a+b)*c", "a+b = 1", "1*c")
+  }
+
+  #[test]
+  fn test_free_variables() -> Result<(), ParseError> {
+    let variables: Vec<String> = parse("y*ln(x)+x^2+3")?.free_variables().into_iter().collect();
+    assert_eq!(variables, vec!["x", "y"]);
+    assert!(parse("sin(1)+2")?.free_variables().is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_bad_bindings() {
+    assert!(parse_bindings("x", parse).is_err());
+    assert!(parse_bindings("x = ", parse).is_err());
+  }
+}
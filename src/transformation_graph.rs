@@ -1,6 +1,7 @@
 use crate::parser::Expression;
 use std::collections::{HashMap, VecDeque, HashSet};
-use crate::tree_transform::Equivalence;
+use crate::tree_transform::{Equivalence, Step};
+use crate::condition::Condition;
 use std::fmt;
 use std::fmt::Formatter;
 use std::rc::Rc;
@@ -10,13 +11,16 @@ use std::ops::Deref;
 struct Node<'b> {
   exp: Rc<Expression>,
   equiv_exps: Vec<(Rc<Expression>, &'b Equivalence, bool)>,
+  // The node this one was first reached from, and the steps that rewrote it into this one.
+  parent: Option<(Rc<Expression>, Vec<Step>)>,
 }
 
 impl<'b> Node<'b> {
   fn new(exp: Rc<Expression>) -> Node<'b> {
     Node {
       exp,
-      equiv_exps: Vec::new()
+      equiv_exps: Vec::new(),
+      parent: None,
     }
   }
 
@@ -38,14 +42,17 @@ pub struct Graph<'b> {
 impl<'b> Graph<'b> {
   // before is already in the graph
   // if after is already in the graph, we still add the edges but return false.
-  pub fn add_node(&mut self, before: Rc<Expression>, after: Rc<Expression>, equiv: &'b Equivalence) -> bool {
+  // steps are how before became after, starting with the application of equiv.
+  pub fn add_node(&mut self, before: Rc<Expression>, after: Rc<Expression>, equiv: &'b Equivalence,
+                  steps: Vec<Step>) -> bool {
     let node_before = self.map.get_mut(before.as_ref()).unwrap();
     node_before.add_equiv_exp(Rc::clone(&after), equiv, false);
 
     let (node_after, is_new) = match self.map.get_mut(after.as_ref()) {
       Some(node_after) => (node_after, false),
       None => {
-        let node_after = Node::new(Rc::clone(&after));
+        let mut node_after = Node::new(Rc::clone(&after));
+        node_after.parent = Some((Rc::clone(&before), steps));
         self.map.insert(after.as_ref().clone(), node_after);
         (self.map.get_mut(after.as_ref()).unwrap(), true)
       },
@@ -76,6 +83,79 @@ impl<'b> Graph<'b> {
   pub fn size(&self) -> usize {
     self.map.len()
   }
+
+  /// How many rewrites connect the expressions, each counted once though it's stored at both ends.
+  pub fn edge_count(&self) -> usize {
+    self.map.values().map(|node| node.equiv_exps.len()).sum::<usize>() / 2
+  }
+
+  pub fn contains(&self, exp: &Expression) -> bool {
+    self.map.contains_key(exp)
+  }
+
+  /// The steps that lead from the root to exp, following the edges along which each node was found.
+  pub fn derivation(&self, exp: &Expression) -> Option<Derivation> {
+    let mut paths = Vec::new();
+    let mut node = self.map.get(exp)?;
+    while let Some((parent, steps)) = node.parent.as_ref() {
+      paths.push(steps.clone());
+      node = self.map.get(parent.as_ref()).unwrap();
+    }
+    Some(Derivation {
+      start: self.root.deref().clone(),
+      steps: paths.into_iter().rev().flatten().collect(),
+    })
+  }
+}
+
+/// A proof that start is equivalent to the last expression in steps (or to itself if there are none).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Derivation {
+  pub start: Expression,
+  pub steps: Vec<Step>,
+}
+
+impl Derivation {
+  /// The same proof, read from the last expression back to start.
+  pub fn reversed(&self) -> Derivation {
+    let mut exps: Vec<&Expression> = vec![&self.start];
+    exps.extend(self.steps.iter().map(|step| &step.exp));
+    let steps = self.steps.iter().enumerate().rev().map(|(i, step)| Step {
+      exp: exps[i].clone(),
+      rule: step.rule.clone(),
+      reverse: !step.reverse,
+      position: step.position.clone(),
+      assumptions: step.assumptions.clone(),
+    }).collect();
+    Derivation { start: exps[exps.len() - 1].clone(), steps }
+  }
+
+  /// Everything assumed along the way, once each.
+  pub fn assumptions(&self) -> Vec<Condition> {
+    let mut assumptions: Vec<Condition> = Vec::new();
+    for assumption in self.steps.iter().flat_map(|step| step.assumptions.iter()) {
+      if !assumptions.contains(assumption) {
+        assumptions.push(assumption.clone());
+      }
+    }
+    assumptions
+  }
+
+  /// This proof followed by other, which must start where this one ends.
+  pub fn then(mut self, other: Derivation) -> Derivation {
+    self.steps.extend(other.steps);
+    self
+  }
+}
+
+impl fmt::Display for Derivation {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    writeln!(f, "{}", self.start)?;
+    for step in self.steps.iter() {
+      writeln!(f, "= {}", step)?;
+    }
+    Ok(())
+  }
 }
 
 impl fmt::Display for Graph<'_> {
@@ -88,8 +168,8 @@ impl fmt::Display for Graph<'_> {
         }
       }
       match relevant_edge {
-        Some((equiv, reverse)) => write!(f, "{}: {} (from {} via {} {:?})\n", d, n.exp, backedge, equiv, reverse),
-        None => write!(f, "{}: {} (from {})\n", d, n.exp, backedge)
+        Some((equiv, reverse)) => writeln!(f, "{}: {} (from {} via {} {:?})", d, n.exp, backedge, equiv, reverse),
+        None => writeln!(f, "{}: {} (from {})", d, n.exp, backedge)
       }
     })
   }
@@ -98,10 +178,7 @@ impl fmt::Display for Graph<'_> {
 pub fn create_graph<'b>(root: Rc<Expression>) -> Graph<'b> {
   let mut map = HashMap::new();
   let root_clone = root.deref().clone();
-  let node = Node {
-    exp: Rc::clone(&root),
-    equiv_exps: Vec::new()
-  };
+  let node = Node::new(Rc::clone(&root));
   map.insert(root_clone, node);
   Graph { map, root }
 }
@@ -1,220 +1,73 @@
-use crate::{var, c, parser::{Expression, expression}};
+use crate::{c, parser::{Expression, Rational}};
+use crate::assumptions::Assumptions;
+use crate::condition::Condition;
+use num_traits::{One, ToPrimitive};
 use std::borrow::BorrowMut;
 use std::collections::HashMap;
 use std::ops::Deref;
 use std::fmt;
 
-type EquivMethod = Box<dyn Fn(&Expression) -> Option<Expression>>;
+type EquivMethod = Box<dyn Fn(&Expression) -> Option<Expression> + Send + Sync>;
 
 /// wants a data structure that encompasses code transformation, before -> after
 #[derive(Default)]
 pub struct Equivalence {
-  before: Expression,
-  after: Expression,
-  forwards_only: bool,
-  method: Option<EquivMethod>,
-  method_name: String,
+  pub(crate) before: Expression,
+  pub(crate) after: Expression,
+  pub(crate) forwards_only: bool,
+  pub(crate) method: Option<EquivMethod>,
+  pub(crate) method_name: String,
+  // must all hold, in terms of the variables in before and after, for the equivalence to apply.
+  // the ones that can't be checked are assumed, and recorded with the rewrite.
+  pub(crate) conditions: Vec<Condition>,
+  // must all be shown to hold, from the expression or the assumptions, for the equivalence to apply.
+  // for rules that would be wrong too often to just assume they hold.
+  pub(crate) requirements: Vec<Condition>,
 }
 
 impl fmt::Display for Equivalence {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self.method {
-      Some(_) => write!(f, "{}", self.method_name),
-      None => write!(f, "{} = {}", self.before, self.after),
+      Some(_) => write!(f, "{}", self.method_name)?,
+      None => write!(f, "{} = {}", self.before, self.after)?,
     }
+    if !self.conditions.is_empty() || !self.requirements.is_empty() {
+      let conditions: Vec<String> = self.requirements.iter().chain(self.conditions.iter())
+        .map(|c| c.to_string()).collect();
+      write!(f, " if {}", conditions.join(", "))?;
+    }
+    Ok(())
   }
 }
 
-// example:
-// x*0
-// x*(a+(-1)*a)
-// x*a + x*((-1)*a)
-// x*a + (x*(-1))*a
-// x*a + (-1)*(x*a)
-// 0
-
-pub fn get_transformations() -> Vec<Equivalence> {
-  vec![
-    // distributive
-    Equivalence {
-      before: expression("a*(b+c)"),
-      after: expression("a*b+a*c"),
-      ..Default::default()
-    },
-    Equivalence {
-      before: expression("(a*b)^c"),
-      after: expression("a^c*b^c"),
-      ..Default::default()
-    },
-    // commutative
-    Equivalence {
-      before: expression("a+b"),
-      after: expression("b+a"),
-      ..Default::default()
-    },
-    Equivalence {
-      before: expression("a*b"),
-      after: expression("b*a"),
-      ..Default::default()
-    },
-    // associative
-    Equivalence {
-      before: expression("a*(b*c)"),
-      after: expression("(a*b)*c"),
-      ..Default::default()
-    },
-    Equivalence {
-      before: expression("a+(b+c)"),
-      after: expression("(a+b)+c"),
-      ..Default::default()
-    },
-    // complex ops
-    Equivalence {
-      before: expression("a/b"),
-      after: expression("a*b^(-1)"),
-      ..Default::default()
-    },
-    Equivalence {
-      before: expression("a^b*a^c"),
-      after: expression("a^(b+c)"),
-      ..Default::default()
-    },
-    Equivalence {
-      before: expression("a-b"),
-      after: expression("a+(-1)*b"),
-      ..Default::default()
-    },
-    // complex rules. yes it's cheating, but it's all good. lol.
-    Equivalence {
-      before: expression("a^2+2*a*b+b^2"),
-      after: expression("(a+b)^2"),
-      ..Default::default()
-    },
+/// A rule implemented by a closure, rather than by before and after patterns, looked up by its name.
+pub fn method_equivalence(name: &str) -> Option<Equivalence> {
+  let method: EquivMethod = match name {
     // simplify expressions with only constants by evaluation
-    Equivalence {
-      method: Some(Box::new(move |exp| exp.eval_const())),
-      method_name: "eval_const".into(),
-      ..Default::default()
-    },
-    Equivalence {
-      method: Some(Box::new(move |exp| group_repeated_operation(exp))),
-      method_name: "group_repeated".into(),
-      ..Default::default()
-    },
-    // Equivalence {
-    // 	method: Some(Box::new(move |exp| split_constants(exp))),
-    // 	method_name: "split_constants".into(),
-    // 	..Default::default()
-    // },
-    Equivalence {
-      method: Some(Box::new(move |exp| split_repeated_operation(exp))),
-      method_name: "split_repeated_operation".into(),
-      ..Default::default()
-    }
-  ]
-}
-
-// These are transformations that you would only ever want to do in the forward direction.
-// And you always want to do them.
-// Anything that might be good about not doing such a simple transformation should be encoded
-// in a more complex transformation.
-// e.g. while you might want a^1 to stick around to eventually do a^1*a^2 -> a^3, having the
-// a^1 around tends to cause cardinality explosions, so a transformation a*a^2 -> a^3 is necessary.
-pub fn get_simple_transformations() -> Vec<Equivalence> {
-  vec![
-    // identity
-    Equivalence {
-      before: expression("0+a"),
-      after: var!("a"),
-      forwards_only: true,
-      ..Default::default()
-    },
-    Equivalence {
-      before: expression("a+0"),
-      after: var!("a"),
-      forwards_only: true,
-      ..Default::default()
-    },
-    // inverse
-    Equivalence {
-      before: expression("a-a"),
-      after: c!(0),
-      forwards_only: true,
-      ..Default::default()
-    },
-    Equivalence {
-      before: expression("a^b^c"),
-      after: expression("a^(b*c)"),
-      forwards_only: true,
-      ..Default::default()
-    },
-    // misc simple
-    Equivalence {
-      before: expression("a*0"),
-      after: c!(0),
-      forwards_only: true,
-      ..Default::default()
-    },
-    Equivalence {
-      before: expression("a^1"),
-      after: expression("a"),
-      forwards_only: true,
-      ..Default::default()
-    },
-    Equivalence {
-      before: expression("a*1"),
-      after: expression("a"),
-      forwards_only: true,
-      ..Default::default()
-    },
-    Equivalence {
-      before: expression("1*a"),
-      after: expression("a"),
-      forwards_only: true,
-      ..Default::default()
-    },
-    Equivalence {
-      method: Some(Box::new(move |exp| multiplicative_inverse(exp))),
-      method_name: "multiplicative_inverse".into(),
-      forwards_only: true,
-      ..Default::default()
-    },
-  ]
+    "eval_const" => Box::new(move |exp| exp.eval_const()),
+    "group_repeated" => Box::new(group_repeated_operation),
+    "split_constants" => Box::new(split_constants),
+    "split_repeated_operation" => Box::new(split_repeated_operation),
+    _ => return None,
+  };
+  Some(Equivalence { method: Some(method), method_name: name.into(), ..Default::default() })
 }
 
 fn split_constants(exp: &Expression) -> Option<Expression> {
   let c = exp.unwrap_constant()?;
-  if c > 1 {
-    Some(c!(1) + c!(c-1))
-  } else if c < -1 {
-    Some(c!(-1) + c!(c+1))
+  let one = Rational::one();
+  if c > one {
+    Some(c!(1) + Expression::Constant(c - one))
+  } else if c < -one.clone() {
+    Some(c!(-1) + Expression::Constant(c + one))
   } else {
     None
   }
 }
 
-// a^0 => 1, a/a => 1, unless a is a constant zero
-fn multiplicative_inverse(exp: &Expression) -> Option<Expression> {
-  match exp {
-    Expression::Power(a, b) => {
-      if b.unwrap_constant()? == 0 && a.eval_const() != Some(Expression::Constant(0)) {
-        return Some(c!(1))
-      }
-    },
-    Expression::Quotient(a, b) => {
-      if a == b && a.eval_const() != Some(Expression::Constant(0)) {
-        return Some(c!(1))
-      }
-    },
-    _ => ()
-  };
-  None
-}
-
 fn group_repeated_operation(exp: &Expression) -> Option<Expression> {
-  match exp.eval_const() {
-    Some(_) => { return None }
-    None => ()
+  if exp.eval_const().is_some() {
+    return None
   }
   match exp {
     // a*a => a^2
@@ -223,10 +76,10 @@ fn group_repeated_operation(exp: &Expression) -> Option<Expression> {
       match b.deref() {
         Expression::Power(c, d) => {
           let d_const = d.unwrap_constant()?;
-          if c == a { return Some(a.deref().clone() ^ (c!(d_const+1))) }
+          if c == a { return Some(a.deref().clone() ^ Expression::Constant(d_const + Rational::one())) }
         },
         // If a==b are both powers, then we'd rather use the a^c*b^c equivalence.
-        // Using group_repeated_operation results in a^c^2 which tends to explode.
+        // Using group_repeated_operation results in (a^c)^2 which tends to explode.
         _ => if a == b {
           return Some(a.deref().clone() ^ c!(2))
         }
@@ -237,12 +90,9 @@ fn group_repeated_operation(exp: &Expression) -> Option<Expression> {
     // a+2*a => 3*a
     Expression::Sum(a, b) => {
       if a == b { return Some(c!(2) * a.deref().clone()) }
-      match b.deref() {
-        Expression::Product(c, d) => {
-          let c_const = c.unwrap_constant()?;
-          if d == a { return Some(c!(c_const+1) * a.deref().clone()) }
-        },
-        _ => ()
+      if let Expression::Product(c, d) = b.deref() {
+        let c_const = c.unwrap_constant()?;
+        if d == a { return Some(Expression::Constant(c_const + Rational::one()) * a.deref().clone()) }
       }
     },
 
@@ -291,10 +141,15 @@ pub fn match_expression_variables<'a>(exp: &'a Expression, match_exp: &Expressio
           match_expression_variables(c, a, assignments) && match_expression_variables(d, b, assignments),
         _ => false
       },
+    Expression::Apply(f, a) =>
+      match exp {
+        Expression::Apply(g, c) => f == g && match_expression_variables(c, a, assignments),
+        _ => false
+      },
   }
 }
 
-fn apply_transform(match_exp: &Expression, assignments: &HashMap<String, &Expression>) -> Expression {
+pub fn apply_transform(match_exp: &Expression, assignments: &HashMap<String, &Expression>) -> Expression {
   match match_exp {
     Expression::Variable(s) => match assignments.get(s) {
       Some(exp) => (**exp).clone(),
@@ -311,102 +166,170 @@ fn apply_transform(match_exp: &Expression, assignments: &HashMap<String, &Expres
       Expression::Quotient(apply_transform(c, assignments).into(), apply_transform(d, assignments).into()),
     Expression::Power(c, d) =>
       Expression::Power(apply_transform(c, assignments).into(), apply_transform(d, assignments).into()),
+    Expression::Apply(f, c) =>
+      Expression::Apply(*f, apply_transform(c, assignments).into()),
   }
 }
 
-fn transform_full_tree(exp: &Expression, before: &Expression, after: &Expression) -> Option<Expression> {
+// The transformed expression, and the conditions that had to be assumed to get it.
+fn transform_full_tree(exp: &Expression, before: &Expression, after: &Expression, equiv: &Equivalence,
+                       known: &Assumptions) -> Option<(Expression, Vec<Condition>)> {
   let mut assignments = HashMap::new();
   if !match_expression_variables(exp, before, assignments.borrow_mut())
   { return None }
-  Some(apply_transform(after, &assignments))
+  for requirement in equiv.requirements.iter() {
+    if requirement.map(&|e| apply_transform(e, &assignments)).check(known) != Some(true) { return None }
+  }
+  let mut assumptions = Vec::new();
+  for condition in equiv.conditions.iter() {
+    let condition = condition.map(&|e| apply_transform(e, &assignments));
+    assumptions.extend(condition.undischarged(known)?);
+  }
+  Some((apply_transform(after, &assignments), assumptions))
+}
+
+/// Path from the root of an expression to one of its subterms:
+/// 0 steps into the left operand, 1 into the right.
+pub type Position = Vec<usize>;
+
+/// An expression reached by applying an equivalence once.
+#[derive(Debug, Clone)]
+pub struct Rewrite {
+  pub exp: Expression,
+  /// Where in the original expression the equivalence was applied.
+  pub position: Position,
+  /// Whether the equivalence was applied from `after` to `before`.
+  pub reverse: bool,
+  /// Conditions of the equivalence that couldn't be checked, so have to be assumed for the rewrite to hold.
+  pub assumptions: Vec<Condition>,
+}
+
+impl Rewrite {
+  // The rewrite of a subterm, as a rewrite of the expression containing it.
+  fn within<F: FnOnce(Expression) -> Expression>(self, side: usize, build: F) -> Rewrite {
+    let mut position = vec![side];
+    position.extend(self.position);
+    Rewrite { exp: build(self.exp), position, reverse: self.reverse, assumptions: self.assumptions }
+  }
+}
+
+/// One step of a derivation: the expression after applying the named rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+  pub exp: Expression,
+  pub rule: String,
+  pub reverse: bool,
+  pub position: Position,
+  pub assumptions: Vec<Condition>,
+}
+
+impl Step {
+  pub fn new(rewrite: Rewrite, equiv: &Equivalence) -> Step {
+    Step {
+      exp: rewrite.exp,
+      rule: equiv.to_string(),
+      reverse: rewrite.reverse,
+      position: rewrite.position,
+      assumptions: rewrite.assumptions,
+    }
+  }
+}
+
+impl fmt::Display for Step {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let position: Vec<String> = self.position.iter().map(|side| side.to_string()).collect();
+    write!(f, "{} (via {}{} at [{}])", self.exp, self.rule, if self.reverse { " reversed" } else { "" },
+           position.join("."))?;
+    if !self.assumptions.is_empty() {
+      let assumptions: Vec<String> = self.assumptions.iter().map(|c| c.to_string()).collect();
+      write!(f, " assuming {}", assumptions.join(", "))?;
+    }
+    Ok(())
+  }
 }
 
 /// Given an expression, and an equivalence, outputs a list of expressions equivalent to it,
-/// that can be reached by applying the equivalence once.
+/// that can be reached by applying the equivalence once, and where and in which direction it was applied.
 /// If equiv.forwards_only, only returns at most a single expression, under the assumption that we want
 /// to make that transformation and move on to the next.
-pub fn transform(exp: &Expression, equiv: &Equivalence) -> Vec<Expression> {
-  let mut transformed = Vec::new();
+/// Conditions of the equivalence that follow from `known` aren't recorded as assumptions of the rewrites.
+pub fn transform(exp: &Expression, equiv: &Equivalence, known: &Assumptions) -> Vec<Rewrite> {
+  let mut transformed = transform_root(exp, equiv, known);
+  if equiv.forwards_only && !transformed.is_empty() { return transformed }
 
-  match equiv.method.as_ref() {
-    Some(m) => match m(exp) {
-      Some(e) => transformed.push(e),
-      None => ()
+  // Kept to a loop over the operands, so each level of a deep expression takes little of the stack.
+  for (side, operand) in operands(exp).iter().flatten().enumerate() {
+    for r in transform(operand, equiv, known).into_iter() {
+      transformed.push(r.within(side, |e| with_operand(exp, side, e)))
     }
+    if equiv.forwards_only && !transformed.is_empty() { return transformed }
+  }
+
+  transformed
+}
+
+// The rewrites of the whole of exp, not of its subterms. A rewrite to exp itself, like eval_const of a constant
+// or a+b -> b+a of x+x, isn't one: it would only add steps that change nothing, and simple rules would never stop.
+fn transform_root(exp: &Expression, equiv: &Equivalence, known: &Assumptions) -> Vec<Rewrite> {
+  let mut transformed = Vec::new();
+  match equiv.method.as_ref() {
+    Some(m) => if let Some(e) = m(exp).filter(|e| e != exp) {
+      transformed.push(Rewrite { exp: e, position: vec![], reverse: false, assumptions: vec![] })
+    },
     None => {
-      match transform_full_tree(exp, &equiv.before, &equiv.after) {
-        Some(e) => transformed.push(e),
-        None => ()
+      let forwards = transform_full_tree(exp, &equiv.before, &equiv.after, equiv, known);
+      if let Some((e, assumptions)) = forwards.filter(|(e, _)| e != exp) {
+        transformed.push(Rewrite { exp: e, position: vec![], reverse: false, assumptions })
       }
       if !equiv.forwards_only {
-        match transform_full_tree(exp, &equiv.after, &equiv.before) {
-          Some(e) => transformed.push(e),
-          None => ()
+        let backwards = transform_full_tree(exp, &equiv.after, &equiv.before, equiv, known);
+        if let Some((e, assumptions)) = backwards.filter(|(e, _)| e != exp) {
+          transformed.push(Rewrite { exp: e, position: vec![], reverse: true, assumptions })
         }
       }
     }
   }
+  transformed
+}
 
-  if equiv.forwards_only && transformed.len() > 0 { return transformed }
-
+fn operands(exp: &Expression) -> [Option<&Expression>; 2] {
   match exp {
-    Expression::Constant(_) => (),
-    Expression::Variable(_) => (),
-    Expression::Product(a, b) => {
-      for e in transform(a, equiv).into_iter() {
-        transformed.push(e * b.deref().clone())
-      }
-      if equiv.forwards_only && transformed.len() > 0 { return transformed }
-      for e in transform(b, equiv).into_iter() {
-        transformed.push(a.deref().clone() * e)
-      }
-    },
-    Expression::Sum(a, b) => {
-      for e in transform(a, equiv).into_iter() {
-        transformed.push(e + b.deref().clone())
-      }
-      if equiv.forwards_only && transformed.len() > 0 { return transformed }
-      for e in transform(b, equiv).into_iter() {
-        transformed.push(a.deref().clone() + e)
-      }
-    },
-    Expression::Difference(a, b) => {
-      for e in transform(a, equiv).into_iter() {
-        transformed.push(e - b.deref().clone())
-      }
-      if equiv.forwards_only && transformed.len() > 0 { return transformed }
-      for e in transform(b, equiv).into_iter() {
-        transformed.push(a.deref().clone() - e)
-      }
-    },
-    Expression::Quotient(a, b) => {
-      for e in transform(a, equiv).into_iter() {
-        transformed.push(e / b.deref().clone())
-      }
-      if equiv.forwards_only && transformed.len() > 0 { return transformed }
-      for e in transform(b, equiv).into_iter() {
-        transformed.push(a.deref().clone() / e)
-      }
-    },
-    Expression::Power(a, b) => {
-      for e in transform(a, equiv).into_iter() {
-        transformed.push(e ^ b.deref().clone())
-      }
-      if equiv.forwards_only && transformed.len() > 0 { return transformed }
-      for e in transform(b, equiv).into_iter() {
-        transformed.push(a.deref().clone() ^ e)
-      }
-    },
+    Expression::Constant(_) | Expression::Variable(_) => [None, None],
+    Expression::Apply(_, a) => [Some(a), None],
+    Expression::Sum(a, b) | Expression::Difference(a, b) | Expression::Product(a, b) |
+    Expression::Quotient(a, b) | Expression::Power(a, b) => [Some(a), Some(b)],
   }
+}
 
-  transformed
+// exp with the operand on the given side replaced by e.
+fn with_operand(exp: &Expression, side: usize, e: Expression) -> Expression {
+  let (a, b) = match exp {
+    Expression::Constant(_) | Expression::Variable(_) => unreachable!("{} has no operands", exp),
+    Expression::Apply(f, _) => return f.apply(e),
+    Expression::Sum(a, b) | Expression::Difference(a, b) | Expression::Product(a, b) |
+    Expression::Quotient(a, b) | Expression::Power(a, b) =>
+      if side == 0 { (e, b.deref().clone()) } else { (a.deref().clone(), e) },
+  };
+  match exp {
+    Expression::Sum(_, _) => a + b,
+    Expression::Difference(_, _) => a - b,
+    Expression::Product(_, _) => a * b,
+    Expression::Quotient(_, _) => a / b,
+    _ => a ^ b,
+  }
 }
 
+// Splitting 1000000*a into a+a+...+a would never be worth it, and would take forever.
+const MAX_SPLIT_REPEATED: i32 = 64;
+
 fn split_repeated_operation(exp: &Expression) -> Option<Expression> {
   match exp {
     // 2*a = a+a
     Expression::Product(a, b) => {
       let c = a.unwrap_constant()?;
+      if !c.is_integer() { return None }
+      let c = c.to_integer().to_i32()?;
+      if c.unsigned_abs() > MAX_SPLIT_REPEATED as u32 { return None }
       if c == 0 { return Some(c!(0))}
       if c < -1 {
         let mut e = c!(-1) * b.deref().clone();
@@ -428,11 +351,13 @@ fn split_repeated_operation(exp: &Expression) -> Option<Expression> {
     // a^2 = a*a
     Expression::Power(a, b) => {
       let d = b.unwrap_constant()?;
-      if d == 0 { return Some(c!(1)) }
+      if !d.is_integer() { return None }
+      let d = d.to_integer().to_i32()?;
+      if d.unsigned_abs() > MAX_SPLIT_REPEATED as u32 { return None }
       if d < -1 {
         let mut e = a.deref().clone() ^ c!(-1);
         for _ in 0..(-d-1) {
-          e = e * a.deref().clone() ^ c!(-1);
+          e = e * (a.deref().clone() ^ c!(-1));
         }
         return Some(e)
       }
@@ -450,19 +375,56 @@ fn split_repeated_operation(exp: &Expression) -> Option<Expression> {
   }
 }
 
-pub fn simplify_via_forward_transform(exp: Expression, transforms: &Vec<Equivalence>) -> Expression {
+/// Returns the simplified expression, and each step taken to get there.
+pub fn simplify_via_forward_transform(exp: Expression, transforms: &[Equivalence], known: &Assumptions)
+                                      -> (Expression, Vec<Step>) {
   let mut simplified = exp;
+  let mut steps = Vec::new();
   loop {
     let mut did_transform = false;
     for equiv in transforms.iter() {
-      let mut transformed = transform(&simplified, equiv);
+      let mut transformed = transform(&simplified, equiv, known);
       if !transformed.is_empty() {
-        simplified = transformed.pop().unwrap();
+        let step = Step::new(transformed.pop().unwrap(), equiv);
+        simplified = step.exp.clone();
+        steps.push(step);
         did_transform = true;
       }
     }
     if !did_transform {
-      return simplified
+      return (simplified, steps)
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::{parse, ParseError};
+
+  #[test]
+  fn test_split_repeated_operation() -> Result<(), ParseError> {
+    assert_eq!(split_repeated_operation(&parse("3*a")?), Some(parse("a+a+a")?));
+    assert_eq!(split_repeated_operation(&parse("a^(-2)")?), Some(parse("a^(-1)*a^(-1)")?));
+    assert_eq!(split_repeated_operation(&parse("65*a")?), None);
+    // too big to split, and too big to negate as an i32
+    assert_eq!(split_repeated_operation(&parse("-2147483648*a")?), None);
+    assert_eq!(split_repeated_operation(&parse("a^(-2147483648)")?), None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_no_identity_rewrites() -> Result<(), ParseError> {
+    let known = Assumptions::new();
+    // eval_const of a constant is the constant
+    let eval_const = method_equivalence("eval_const").unwrap();
+    assert!(transform(&parse("2")?, &eval_const, &known).is_empty());
+    assert!(transform(&parse("x*0+2")?, &eval_const, &known).is_empty());
+    let commute = Equivalence { before: parse("a+b")?, after: parse("b+a")?, ..Default::default() };
+    assert!(transform(&parse("x+x")?, &commute, &known).is_empty());
+    // so a simple rule that leaves everything the same doesn't keep applying it
+    let simple = [Equivalence { forwards_only: true, ..method_equivalence("eval_const").unwrap() }];
+    assert_eq!(simplify_via_forward_transform(parse("x*1+2")?, &simple, &known), (parse("x*1+2")?, vec![]));
+    Ok(())
+  }
+}
@@ -0,0 +1,11 @@
+// The macros are exported, so they have to work from outside the crate too.
+
+use symbolic::{c, parse, var, ParseError};
+
+#[test]
+fn test_macros() -> Result<(), ParseError> {
+  assert_eq!(var!("x"), parse("x")?);
+  assert_eq!(c!(2), parse("2")?);
+  assert_eq!(var!("x") + c!(1), parse("x+1")?);
+  Ok(())
+}